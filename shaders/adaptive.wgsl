@@ -0,0 +1,65 @@
+///#include "utils/variance.wgsl"
+
+override WIDTH: u32;
+override HEIGHT: u32;
+// The relative standard error of the mean luminance below which pixels are done, or zero for
+// pixels to be done only once converged
+override TARGET_NOISE: f32 = 0.0;
+// The number of samples before the error of a pixel is estimated
+override MIN_SAMPLES: u32 = 16u;
+
+struct Counts {
+    // The number of pixels of the image which are not done
+    active_pixels: atomic<u32>,
+    // The total number of samples of the pixels, split into two words
+    samples_low: atomic<u32>,
+    samples_high: atomic<u32>,
+}
+
+@group(0) @binding(0)
+var variance_target: texture_storage_2d<rgba32float, read>;
+
+@group(0) @binding(1)
+var<storage, read_write> counts: Counts;
+
+var<workgroup> local_active_pixels: atomic<u32>;
+var<workgroup> local_samples: atomic<u32>;
+
+@compute
+@workgroup_size(16, 16, 1)
+fn count_active(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) index: u32) {
+    if index == 0u {
+        atomicStore(&local_active_pixels, 0u);
+        atomicStore(&local_samples, 0u);
+    }
+    workgroupBarrier();
+
+    if id.x < WIDTH && id.y < HEIGHT {
+        let variance = textureLoad(variance_target, id.xy);
+        atomicAdd(&local_samples, u32(variance.z));
+        if pixel_active(variance) {
+            atomicAdd(&local_active_pixels, 1u);
+        }
+    }
+
+    workgroupBarrier();
+    if index == 0u {
+        atomicAdd(&counts.active_pixels, atomicLoad(&local_active_pixels));
+        let samples = atomicLoad(&local_samples);
+        let prev = atomicAdd(&counts.samples_low, samples);
+        if prev > 0xFFFFFFFFu - samples {
+            atomicAdd(&counts.samples_high, 1u);
+        }
+    }
+}
+
+// Whether a pixel is neither converged nor below the target noise
+fn pixel_active(variance: vec4f) -> bool {
+    if variance.a != 0.0 {
+        return false;
+    }
+    if TARGET_NOISE == 0.0 || variance.z < f32(max(MIN_SAMPLES, 2u)) {
+        return true;
+    }
+    return variance_relative_error(variance.x, variance.y, variance.z) > TARGET_NOISE;
+}
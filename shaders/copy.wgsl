@@ -29,5 +29,6 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4f {
-    return textureSample(t_compute, s_compute, in.tex_coords);
+    // The preview is always opaque, transparency only matters for the saved image
+    return vec4(textureSample(t_compute, s_compute, in.tex_coords).rgb, 1.0);
 }
@@ -7,6 +7,7 @@ struct Intersection {
     tex_coord: vec2f,
     transform: u32,
     material: u32,
+    primitive: u32,
 }
 
 fn intersection_flip_normal(intersection: ptr<function, Intersection>, ray: Ray) {
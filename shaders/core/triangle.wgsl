@@ -13,6 +13,7 @@ var<storage, read> material_indices: array<u32>;
 struct Vertex {
     pos: vec3f,
     normal: vec3f,
+    tangent: vec4f,
     tex_coord: vec2f
 }
 
@@ -63,28 +64,32 @@ fn triangle_intersect(triangle: Triangle, ray: Ray,
         return false;
     }
 
-    let tangent = tangent(triangle);
+    let normal = normalize((e0 * vertices[triangle.v0 + offset].normal + e1 * vertices[triangle.v1 + offset].normal + e2 * vertices[triangle.v2 + offset].normal) / det);
+    let tangent = (e0 * vertices[triangle.v0 + offset].tangent.xyz + e1 * vertices[triangle.v1 + offset].tangent.xyz + e2 * vertices[triangle.v2 + offset].tangent.xyz) / det;
+    let handedness = select(1.0, -1.0, vertices[triangle.v0 + offset].tangent.w < 0.0);
     (*intersection).t = t;
-    (*intersection).normal = normalize((e0 * vertices[triangle.v0 + offset].normal + e1 * vertices[triangle.v1 + offset].normal + e2 * vertices[triangle.v2 + offset].normal) / det);
-    (*intersection).tangent = normalize(tangent - dot(tangent, (*intersection).normal) * (*intersection).normal);
-    (*intersection).bitangent = cross((*intersection).normal, (*intersection).tangent);
+    (*intersection).normal = normal;
+    (*intersection).tangent = orthogonal_tangent(normal, tangent);
+    (*intersection).bitangent = handedness * cross(normal, (*intersection).tangent);
     (*intersection).tex_coord = (e0 * vertices[triangle.v0 + offset].tex_coord + e1 * vertices[triangle.v1 + offset].tex_coord + e2 * vertices[triangle.v2 + offset].tex_coord) / det;
     (*intersection).transform = transform_indices[triangle.primitive];
     (*intersection).material = material_indices[triangle.primitive];
+    (*intersection).primitive = triangle.primitive;
 
     return true;
 }
 
-fn tangent(triangle: Triangle) -> vec3f {
-    let offset = offsets[triangle.primitive];
-    let e0 = vertices[triangle.v1 + offset].pos - vertices[triangle.v0 + offset].pos;
-    let e1 = vertices[triangle.v2 + offset].pos - vertices[triangle.v1 + offset].pos;
-
-    let duv0 = vertices[triangle.v1 + offset].tex_coord - vertices[triangle.v0 + offset].tex_coord;
-    let duv1 = vertices[triangle.v2 + offset].tex_coord - vertices[triangle.v1 + offset].tex_coord;
-
-    let det = duv0.x * duv1.y - duv0.y * duv1.x;
-    return (duv1.y * e0 - duv0.y * e1) / det;
+// Gram-Schmidt orthogonalizes the interpolated tangent against the normal, falling back to an
+// arbitrary perpendicular direction if the tangent is missing or degenerate
+fn orthogonal_tangent(normal: vec3f, tangent: vec3f) -> vec3f {
+    let t = tangent - dot(tangent, normal) * normal;
+    if dot(t, t) > 1e-12 {
+        return normalize(t);
+    }
+    if abs(normal.x) > abs(normal.z) {
+        return normalize(vec3(-normal.y, normal.x, 0.0));
+    }
+    return normalize(vec3(0.0, -normal.z, normal.y));
 }
 
 fn max_dim(v: vec3f) -> u32 {
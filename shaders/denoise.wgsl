@@ -0,0 +1,112 @@
+override NUM_ITERATIONS: u32;
+// The layers of the AOV target holding the guides
+override ALBEDO_LAYER: u32;
+override NORMAL_LAYER: u32;
+override DEPTH_LAYER: u32;
+// The sensitivities of the edge-stopping functions, the one of the radiance is halved with each
+// iteration
+override COLOR_PHI: f32 = 1.0;
+override ALBEDO_PHI: f32 = 0.1;
+override NORMAL_PHI: f32 = 0.5;
+override DEPTH_PHI: f32 = 0.01;
+
+@group(0) @binding(0)
+var src_texture: texture_storage_2d<rgba32float, read>;
+
+@group(0) @binding(1)
+var dst_texture: texture_storage_2d<rgba32float, write>;
+
+@group(0) @binding(2)
+var aov_target: texture_storage_2d_array<rgba32float, read>;
+
+// The number of samples of each pixel in the third channel
+@group(0) @binding(3)
+var variance_target: texture_storage_2d<rgba32float, read>;
+
+// The index of the iteration, whose taps are 2^iteration pixels apart
+var<push_constant> iteration: u32;
+
+struct Guide {
+    color: vec3f,
+    albedo: vec3f,
+    normal: vec3f,
+    depth: f32,
+}
+
+// One iteration of the edge-avoiding à-trous wavelet transform of Dammertz et al., a 5x5 B3 spline
+// kernel whose taps are weighted by the similarity of the first hits
+@compute
+@workgroup_size(16, 16, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2i(textureDimensions(src_texture));
+    let pix = vec2i(id.xy);
+    let step = 1 << iteration;
+    let color_phi = COLOR_PHI / f32(1u << iteration);
+    var kernel = array(1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+
+    let center = denoise_guide(pix);
+    var sum = vec4(0.0);
+    var total_weight = 0.0;
+    for (var y = -2; y <= 2; y++) {
+        for (var x = -2; x <= 2; x++) {
+            let tap = clamp(pix + vec2(x, y) * step, vec2(0), size - 1);
+            let guide = denoise_guide(tap);
+
+            // Radiance differences are relative, as its scale depends on the scene
+            let color = (center.color - guide.color) /
+                        max(luminance(center.color) + luminance(guide.color), 1e-6);
+            let albedo = center.albedo - guide.albedo;
+            let normal = center.normal - guide.normal;
+            let depth = (center.depth - guide.depth) / max(center.depth, 1e-3);
+            let weight = exp(-dot(color, color) / color_phi) *
+                         exp(-dot(albedo, albedo) / ALBEDO_PHI) *
+                         exp(-dot(normal, normal) / (f32(step * step) * NORMAL_PHI)) *
+                         exp(-depth * depth / DEPTH_PHI) *
+                         kernel[x + 2] * kernel[y + 2];
+
+            sum += weight * denoise_load(tap);
+            total_weight += weight;
+        }
+    }
+
+    // The center tap has a weight of one before the kernel, so the total is never zero
+    var filtered = sum / total_weight;
+    if iteration == NUM_ITERATIONS - 1u {
+        // Undo the average, as the result is divided by the number of samples like the render target
+        filtered *= denoise_samples(pix);
+    }
+    textureStore(dst_texture, pix, filtered);
+}
+
+// The radiance of a pixel, whose sum is averaged over the samples by the first iteration
+fn denoise_load(pix: vec2i) -> vec4f {
+    let value = textureLoad(src_texture, pix);
+    if iteration == 0u {
+        return value / denoise_samples(pix);
+    }
+    return value;
+}
+
+fn denoise_samples(pix: vec2i) -> f32 {
+    return max(textureLoad(variance_target, pix).z, 1.0);
+}
+
+// The unpremultiplied radiance and the first hit at a pixel, whose sums are averaged by the counts
+// of hits in alpha
+fn denoise_guide(pix: vec2i) -> Guide {
+    let value = denoise_load(pix);
+    let albedo = textureLoad(aov_target, pix, ALBEDO_LAYER);
+    let normal = textureLoad(aov_target, pix, NORMAL_LAYER);
+    let depth = textureLoad(aov_target, pix, DEPTH_LAYER);
+    let coverage = max(albedo.a, 1.0);
+    return Guide(
+        value.rgb / max(value.a, 1e-6),
+        albedo.rgb / coverage,
+        normal.xyz / coverage,
+        depth.x / coverage,
+    );
+}
+
+fn luminance(color: vec3f) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
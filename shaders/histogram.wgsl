@@ -0,0 +1,95 @@
+override WIDTH: u32;
+override HEIGHT: u32;
+// The range of log2 luminance covered by the histogram, darker pixels fall into the first bin
+override MIN_LOG_LUMINANCE: f32 = -16.0;
+override LOG_LUMINANCE_RANGE: f32 = 32.0;
+// The fractions of pixels below which and above which pixels are ignored
+override LOW_PERCENTILE: f32 = 0.5;
+override HIGH_PERCENTILE: f32 = 0.95;
+// The luminance the average is exposed to, which is middle gray
+override KEY_VALUE: f32 = 0.18;
+
+const NUM_BINS: u32 = 256u;
+
+@group(0) @binding(0)
+var src_texture: texture_storage_2d<rgba32float, read>;
+
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, NUM_BINS>;
+
+@group(0) @binding(2)
+var<storage, read_write> exposure: f32;
+
+var<workgroup> local_bins: array<atomic<u32>, NUM_BINS>;
+var<workgroup> bins: array<u32, NUM_BINS>;
+
+@compute
+@workgroup_size(16, 16, 1)
+fn build_histogram(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) index: u32) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+
+    if id.x < WIDTH && id.y < HEIGHT {
+        // Transparent background samples contribute no color, so unpremultiply by their coverage,
+        // which also divides by the number of samples
+        let value = textureLoad(src_texture, id.xy);
+        if value.a > 0.0 {
+            let luminance = dot(value.rgb / value.a, vec3(0.2126, 0.7152, 0.0722));
+            atomicAdd(&local_bins[histogram_bin(luminance)], 1u);
+        }
+    }
+
+    workgroupBarrier();
+    atomicAdd(&histogram[index], atomicLoad(&local_bins[index]));
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn average_histogram(@builtin(local_invocation_index) index: u32) {
+    bins[index] = atomicLoad(&histogram[index]);
+    atomicStore(&histogram[index], 0u);
+    workgroupBarrier();
+
+    if index != 0u {
+        return;
+    }
+
+    // The first bin holds black pixels, which don't take part in the average
+    var total = 0u;
+    for (var i = 1u; i < NUM_BINS; i++) {
+        total += bins[i];
+    }
+    let low = f32(total) * LOW_PERCENTILE;
+    let high = f32(total) * HIGH_PERCENTILE;
+
+    // Average the log luminance of the pixels between the percentiles
+    var count = 0.0;
+    var sum = 0.0;
+    var weight = 0.0;
+    for (var i = 1u; i < NUM_BINS; i++) {
+        let num_pixels = f32(bins[i]);
+        let inside = clamp(count + num_pixels, low, high) - clamp(count, low, high);
+        sum += inside * histogram_log_luminance(i);
+        weight += inside;
+        count += num_pixels;
+    }
+
+    var log_average = 0.0;
+    if weight > 0.0 {
+        log_average = sum / weight;
+    }
+    exposure = KEY_VALUE / exp2(log_average);
+}
+
+fn histogram_bin(luminance: f32) -> u32 {
+    if luminance < exp2(MIN_LOG_LUMINANCE) {
+        return 0u;
+    }
+    let t = saturate((log2(luminance) - MIN_LOG_LUMINANCE) / LOG_LUMINANCE_RANGE);
+    return u32(t * f32(NUM_BINS - 2u)) + 1u;
+}
+
+// The log luminance at the center of a bin
+fn histogram_log_luminance(bin: u32) -> f32 {
+    return MIN_LOG_LUMINANCE + (f32(bin) - 0.5) / f32(NUM_BINS - 2u) * LOG_LUMINANCE_RANGE;
+}
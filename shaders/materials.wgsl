@@ -5,10 +5,25 @@ struct Material {
     base_color_texture: u32,
     metallic_roughness_texture: u32,
     normal_texture: u32,
-    emissive_texture: u32
+    emissive_texture: u32,
+    occlusion_texture: u32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+}
+
+// The diffuse and specular lobes of a BRDF, kept apart so that occlusion only attenuates the
+// diffuse lobe
+struct BrdfLobes {
+    diffuse: vec3f,
+    specular: vec3f,
 }
 
 fn material_brdf(intersection: Intersection, n: vec3f, h: vec3f, wi: vec3f, wo: vec3f) -> vec3f {
+    let lobes = material_brdf_lobes(intersection, n, h, wi, wo);
+    return lobes.diffuse + lobes.specular;
+}
+
+fn material_brdf_lobes(intersection: Intersection, n: vec3f, h: vec3f, wi: vec3f, wo: vec3f) -> BrdfLobes {
     let material = materials[intersection.material];
     let base_color = sample_texture(material.base_color_texture, intersection.tex_coord);
     let metallic_roughness = sample_texture(material.metallic_roughness_texture, intersection.tex_coord);
@@ -26,9 +41,34 @@ fn material_brdf(intersection: Intersection, n: vec3f, h: vec3f, wi: vec3f, wo:
 
     let alpha2 = alpha * alpha;
     let diffuse = (1.0 - f) / PI * mix(base_color, vec3(0.0), metallic);
-    var specular = f * microfacet_dist(alpha2, ndoth) * masking_shadowing(alpha2, ndotl, ndotv) / (4.0 * ndotl * ndotv);
+    let specular = f * microfacet_dist(alpha2, ndoth) * masking_shadowing(alpha2, ndotl, ndotv) / (4.0 * ndotl * ndotv);
+
+    return BrdfLobes(diffuse, specular);
+}
+
+// The base color of the material, which is the albedo of the diffuse lobe
+fn material_albedo(intersection: Intersection) -> vec3f {
+    let material = materials[intersection.material];
+    return sample_texture(material.base_color_texture, intersection.tex_coord);
+}
 
-    return diffuse + specular;
+// The attenuation of indirect diffuse lighting by the occlusion texture, which direct lighting ignores
+fn material_occlusion(intersection: Intersection) -> f32 {
+    let material = materials[intersection.material];
+    if !USE_OCCLUSION || material.occlusion_texture == NO_TEXTURE {
+        return 1.0;
+    }
+    let occlusion = sample_texture(material.occlusion_texture, intersection.tex_coord).r;
+    return 1.0 + material.occlusion_strength * (occlusion - 1.0);
+}
+
+fn material_normal(intersection: Intersection) -> vec3f {
+    let material = materials[intersection.material];
+    let normal_in_tangent = (2.0 * sample_texture(material.normal_texture, intersection.tex_coord) - 1.0) *
+                            vec3(material.normal_scale, material.normal_scale, 1.0);
+    return normalize(normal_in_tangent.x * intersection.tangent +
+                     normal_in_tangent.y * intersection.bitangent +
+                     normal_in_tangent.z * intersection.normal);
 }
 
 fn material_sample_h(intersection: Intersection, state: ptr<function, u32>, n: vec3f) -> vec3f {
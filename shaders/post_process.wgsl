@@ -1,4 +1,17 @@
-override NUM_SAMPLES: u32;
+///#include "utils/constants.wgsl"
+///#include "utils/rand.wgsl"
+///#include "utils/tone_map.wgsl"
+
+override EXPOSURE: f32 = 1.0;
+override TONE_MAP: u32 = TONE_MAP_REINHARD;
+// The radiance mapped to white, or zero for none
+override WHITE_POINT: f32 = 0.0;
+override DITHER: u32 = DITHER_NONE;
+override BLUE_NOISE_SIZE: u32 = 1u;
+
+const DITHER_NONE: u32 = 0u;
+const DITHER_TRIANGULAR: u32 = 1u;
+const DITHER_BLUE_NOISE: u32 = 2u;
 
 @group(0) @binding(0)
 var src_texture: texture_storage_2d<rgba32float, read>;
@@ -6,11 +19,62 @@ var src_texture: texture_storage_2d<rgba32float, read>;
 @group(0) @binding(1)
 var dst_texture: texture_storage_2d<rgba8unorm, write>;
 
+// The exposure found by the luminance histogram, or one
+@group(0) @binding(2)
+var<storage, read> auto_exposure: f32;
+
+// The image before quantization to 8 bits, for 16 bit outputs
+@group(0) @binding(3)
+var precise_texture: texture_storage_2d<rgba32float, write>;
+
+// The thresholds of a tiled blue noise mask
+@group(0) @binding(4)
+var<storage, read> blue_noise: array<f32>;
+
+// The number of samples of each pixel in the third channel
+@group(0) @binding(5)
+var variance_target: texture_storage_2d<rgba32float, read>;
+
 @compute
 @workgroup_size(16, 16, 1)
 fn main(@builtin(global_invocation_id) id: vec3<u32>) {
-    var color = textureLoad(src_texture, id.xy).xyz / f32(NUM_SAMPLES);
-    color /= color + vec3(1.0);
-    color = pow(color, vec3(1.0 / 2.2));
-    textureStore(dst_texture, id.xy, vec4(color, 1.0));
+    let samples = textureLoad(variance_target, id.xy).z;
+    let value = textureLoad(src_texture, id.xy) / max(samples, 1.0);
+    var color = value.xyz;
+    // Transparent background samples contribute no color, so unpremultiply by their coverage
+    if value.a > 0.0 {
+        color /= value.a;
+    }
+    color = tone_map(color * EXPOSURE * auto_exposure, TONE_MAP, WHITE_POINT);
+    color = srgb_oetf(color);
+    textureStore(precise_texture, id.xy, vec4(color, value.a));
+    textureStore(dst_texture, id.xy, vec4(color + dither(id.xy) / 255.0, value.a));
+}
+
+// Returns the noise added to each channel before quantization, in units of the 8 bit step
+fn dither(pix: vec2u) -> vec3f {
+    switch DITHER {
+        case DITHER_TRIANGULAR: {
+            // The sum of two uniform variables has a triangular distribution
+            var state = rand_init(pix, textureDimensions(src_texture), 0u);
+            let a = vec3(rand(&state), rand(&state), rand(&state));
+            let b = vec3(rand(&state), rand(&state), rand(&state));
+            return a + b - 1.0;
+        }
+        case DITHER_BLUE_NOISE: {
+            // Offset the mask for each channel to decorrelate them
+            let r = blue_noise_threshold(pix);
+            let g = blue_noise_threshold(pix + vec2(BLUE_NOISE_SIZE / 2u, 0u));
+            let b = blue_noise_threshold(pix + vec2(0u, BLUE_NOISE_SIZE / 2u));
+            return vec3(r, g, b) - 0.5;
+        }
+        default: {
+            return vec3(0.0);
+        }
+    }
+}
+
+fn blue_noise_threshold(pix: vec2u) -> f32 {
+    let pos = pix % BLUE_NOISE_SIZE;
+    return blue_noise[pos.y * BLUE_NOISE_SIZE + pos.x];
 }
@@ -3,21 +3,51 @@
 ///#include "core/triangle.wgsl"
 
 ///#include "scene/camera.wgsl"
+///#include "scene/lights.wgsl"
 ///#include "scene/scene.wgsl"
+///#include "scene/sky.wgsl"
 
 ///#include "utils/aabb.wgsl"
 ///#include "utils/constants.wgsl"
 ///#include "utils/interval.wgsl"
 ///#include "utils/rand.wgsl"
+///#include "utils/variance.wgsl"
 
 ///#include "materials.wgsl"
 ///#include "textures.wgsl"
 
 override MAX_DEPTH: u32;
+override USE_OCCLUSION: bool;
+// The layers of the AOV target holding each auxiliary output, if selected
+override AOV_ALBEDO: u32 = NO_AOV;
+override AOV_NORMAL: u32 = NO_AOV;
+override AOV_POSITION: u32 = NO_AOV;
+override AOV_DEPTH: u32 = NO_AOV;
+override AOV_UV: u32 = NO_AOV;
+override AOV_MATERIAL: u32 = NO_AOV;
+override AOV_PRIMITIVE: u32 = NO_AOV;
+
+// The relative standard error of the mean luminance below which pixels stop being sampled, or
+// zero to sample all pixels
+override NOISE_THRESHOLD: f32 = 0.0;
+// The number of samples before the error of a pixel is estimated
+override MIN_SAMPLES: u32 = 16u;
+// The number of samples after which a pixel stops being sampled
+override MAX_SAMPLES: u32 = 0xFFFFFFFFu;
+
+const NO_AOV: u32 = 0xFFFFFFFFu;
 
 @group(0) @binding(0)
 var render_target: texture_storage_2d<rgba32float, read_write>;
 
+@group(0) @binding(1)
+var aov_target: texture_storage_2d_array<rgba32float, read_write>;
+
+// The sums of the luminance and its square, the number of samples and whether the pixel is
+// converged
+@group(0) @binding(2)
+var variance_target: texture_storage_2d<rgba32float, read_write>;
+
 var<push_constant> sample: u32;
 
 struct Path {
@@ -29,21 +59,44 @@ struct Path {
 @workgroup_size(16, 16, 1)
 fn main(@builtin(global_invocation_id) id: vec3<u32>) {
     let size = textureDimensions(render_target);
+    let variance = textureLoad(variance_target, id.xy);
+    if variance.a != 0.0 {
+        return;
+    }
     var rand_state = rand_init(id.xy, size, sample);
 
     var ray = camera_gen_ray(scene.camera, id.xy, &rand_state);
+    if all(ray.dir == vec3(0.0)) {
+        // Outside of the image circle of a fisheye
+        let prev_color = textureLoad(render_target, id.xy);
+        textureStore(render_target, id.xy, prev_color + vec4(0.0, 0.0, 0.0, 1.0));
+        variance_accumulate(id.xy, variance, vec3(0.0));
+        return;
+    }
+    let vignetting = camera_vignetting(scene.camera, ray);
     var paths = array<Path, 50>();
+    var alpha = 1.0;
     var depth = 0u;
     for (; depth < MAX_DEPTH; depth++) {
         var intersection = Intersection();
-        if scene_intersect(ray, &intersection) {
+        let hit = scene_intersect(ray, &intersection);
+
+        // Area lights are seen by camera rays and end paths after a bounce, where lights_direct has
+        // already added their light
+        var emission: vec3f;
+        if lights_intersect(ray, intersection.t, &emission) {
+            paths[depth].coefficient = vec3(0.0, 0.0, 0.0);
+            paths[depth].constant = select(vec3(0.0), emission, depth == 0);
+            break;
+        }
+
+        if hit {
             // Materials
             intersection_flip_normal(&intersection, ray);
-            let material = materials[intersection.material];
-            let normal_in_tangent = sample_texture(material.normal_texture, intersection.tex_coord);
-            let normal = normalize(normal_in_tangent.x * intersection.tangent +
-                         normal_in_tangent.y * intersection.bitangent +
-                         normal_in_tangent.z * intersection.normal);
+            let normal = material_normal(intersection);
+            if depth == 0 {
+                aov_accumulate(id.xy, intersection, normal, ray);
+            }
 
             if dot(ray.dir, normal) >= 0.0 {
                 paths[depth].coefficient = vec3(0.0, 0.0, 0.0);
@@ -75,11 +128,23 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
             ray.dir = wi;
 
             // Integration
-            paths[depth].coefficient = material_brdf(intersection, normal, h, wi, wo) / pdf;
-            paths[depth].constant = vec3(0.0, 0.0, 0.0); //sample_texture(material.emissive_texture, intersection.tex_coord);
+            let brdf = material_brdf_lobes(intersection, normal, h, wi, wo);
+            paths[depth].coefficient = (brdf.diffuse * material_occlusion(intersection) + brdf.specular) / pdf;
+            paths[depth].constant = sun_direct(intersection, ray.orig, normal, wo, &rand_state) +
+                                    lights_direct(intersection, ray.orig, normal, wo, &rand_state); //sample_texture(material.emissive_texture, intersection.tex_coord);
         } else {
             // Background
-            paths[depth].coefficient = sample_panorama(scene.hdri, normalize(ray.dir));
+            if depth == 0 {
+                let background = scene_background(normalize(ray.dir));
+                paths[depth].coefficient = background.rgb;
+                alpha = background.a;
+            } else if portals_intersect(ray) {
+                // The environment seen through portals is sampled by lights_direct
+                paths[depth].coefficient = vec3(0.0, 0.0, 0.0);
+            } else {
+                // The sun is only reached through sun_direct after a bounce
+                paths[depth].coefficient = scene_environment(normalize(ray.dir));
+            }
             paths[depth].constant = vec3(0.0, 0.0, 0.0);
             break;
         }
@@ -93,5 +158,90 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
     }
 
     let prev_color = textureLoad(render_target, id.xy);
-    textureStore(render_target, id.xy, prev_color + vec4(color, 1.0));
+    textureStore(render_target, id.xy, prev_color + vec4(vignetting * color, alpha));
+    variance_accumulate(id.xy, variance, vignetting * color);
+}
+
+// Adds a sample to the statistics of a pixel, which converges once the standard error of its mean
+// luminance falls below the noise threshold relative to the mean
+fn variance_accumulate(pix: vec2u, variance: vec4f, color: vec3f) {
+    let luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    let sum = variance.x + luminance;
+    let sum_squares = variance.y + luminance * luminance;
+    let n = variance.z + 1.0;
+
+    var converged = n >= f32(MAX_SAMPLES);
+    if NOISE_THRESHOLD > 0.0 && n >= f32(max(MIN_SAMPLES, 2u)) {
+        converged |= variance_relative_error(sum, sum_squares, n) <= NOISE_THRESHOLD;
+    }
+    textureStore(variance_target, pix, vec4(sum, sum_squares, n, f32(converged)));
+}
+
+// Accumulates the auxiliary outputs of a first hit, with a coverage of one in alpha. The indices
+// are taken from the first sample instead, as their average would be meaningless
+fn aov_accumulate(pix: vec2u, intersection: Intersection, normal: vec3f, ray: Ray) {
+    aov_add(pix, AOV_ALBEDO, vec4(material_albedo(intersection), 1.0));
+    aov_add(pix, AOV_NORMAL, vec4(normal, 1.0));
+    aov_add(pix, AOV_POSITION, vec4(ray_at(ray, intersection.t), 1.0));
+    aov_add(pix, AOV_DEPTH, vec4(intersection.t * length(ray.dir), 0.0, 0.0, 1.0));
+    aov_add(pix, AOV_UV, vec4(intersection.tex_coord, 0.0, 1.0));
+    if sample == 0u {
+        aov_set(pix, AOV_MATERIAL, vec4(f32(intersection.material), 0.0, 0.0, 1.0));
+        aov_set(pix, AOV_PRIMITIVE, vec4(f32(intersection.primitive), 0.0, 0.0, 1.0));
+    }
+}
+
+fn aov_add(pix: vec2u, layer: u32, value: vec4f) {
+    if layer != NO_AOV {
+        let prev = textureLoad(aov_target, pix, layer);
+        textureStore(aov_target, pix, layer, prev + value);
+    }
+}
+
+fn aov_set(pix: vec2u, layer: u32, value: vec4f) {
+    if layer != NO_AOV {
+        textureStore(aov_target, pix, layer, value);
+    }
+}
+
+// Next event estimation of the sun disk of the sky
+fn sun_direct(intersection: Intersection, pos: vec3f, normal: vec3f, wo: vec3f, rand_state: ptr<function, u32>) -> vec3f {
+    if scene.sky.enabled == 0u {
+        return vec3(0.0);
+    }
+
+    let wi = sky_sample_sun(scene.sky, rand_state);
+    let ndotl = dot(normal, wi);
+    let shadow_ray = Ray(pos, wi);
+    let t_max = bitcast<f32>(0x7F800000);
+    if ndotl <= 0.0 || dot(normal, wo) <= 0.0 || scene_occluded(shadow_ray, t_max) ||
+       lights_occluded(shadow_ray, t_max, scene.num_lights) {
+        return vec3(0.0);
+    }
+
+    let h = normalize(wo + wi);
+    let radiance = scene_sun(wi);
+    return material_brdf(intersection, normal, h, wi, wo) * radiance * ndotl * sky_sun_solid_angle(scene.sky);
+}
+
+// Direct lighting from the punctual and area lights of the scene, and the environment through portals
+fn lights_direct(intersection: Intersection, pos: vec3f, normal: vec3f, wo: vec3f, rand_state: ptr<function, u32>) -> vec3f {
+    var radiance = vec3(0.0);
+    if dot(normal, wo) <= 0.0 {
+        return radiance;
+    }
+
+    for (var i = 0u; i < scene.num_lights; i++) {
+        let sample = light_sample(lights[i], pos, rand_state);
+        let ndotl = dot(normal, sample.wi);
+        let shadow_ray = Ray(pos, sample.wi);
+        if ndotl <= 0.0 || all(sample.irradiance == vec3(0.0)) ||
+           scene_occluded(shadow_ray, sample.distance) || lights_occluded(shadow_ray, sample.distance, i) {
+            continue;
+        }
+
+        let h = normalize(wo + sample.wi);
+        radiance += material_brdf(intersection, normal, h, sample.wi, wo) * sample.irradiance * ndotl;
+    }
+    return radiance;
 }
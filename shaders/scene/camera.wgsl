@@ -1,12 +1,132 @@
+const CAMERA_PERSPECTIVE: u32 = 0u;
+const CAMERA_ORTHOGRAPHIC: u32 = 1u;
+const CAMERA_EQUIRECTANGULAR: u32 = 2u;
+const CAMERA_STEREO_PANORAMA: u32 = 3u;
+const CAMERA_EQUIDISTANT: u32 = 4u;
+const CAMERA_EQUISOLID: u32 = 5u;
+const CAMERA_STEREOGRAPHIC: u32 = 6u;
+const CAMERA_BROWN_CONRADY: u32 = 7u;
+
 struct Camera {
+    kind: u32,
     transform: mat4x4f,
     pix_orig: vec3f,
     pix_dx: vec3f,
     pix_dy: vec3f,
+    lens_radius: f32,
+    focus_distance: f32,
+    aperture_blades: u32,
+    aperture_rotation: f32,
+    interpupillary_distance: f32,
+    max_angle: f32,
+    radial_distortion: vec3f,
+    tangential_distortion: vec2f,
+    vignetting: u32,
+    exposure: f32,
 }
 
 fn camera_gen_ray(camera: Camera, pix: vec2u, rand_state: ptr<function, u32>) -> Ray {
     let pix_xy = vec2f(pix) + rand_square(rand_state);
     let pix_pos = camera.pix_orig + pix_xy.x * camera.pix_dx + pix_xy.y * camera.pix_dy;
-    return Ray((camera.transform * vec4(0.0, 0.0, 0.0, 1.0)).xyz, (camera.transform * vec4(pix_pos, 0.0)).xyz);
+    if camera.kind == CAMERA_EQUIRECTANGULAR || camera.kind == CAMERA_STEREO_PANORAMA {
+        return camera_panorama_ray(camera, pix_pos.xy);
+    }
+    if camera.kind == CAMERA_EQUIDISTANT || camera.kind == CAMERA_EQUISOLID || camera.kind == CAMERA_STEREOGRAPHIC {
+        return camera_fisheye_ray(camera, pix_pos.xy);
+    }
+
+    var origin = vec3(0.0);
+    var dir = pix_pos;
+    if camera.kind == CAMERA_ORTHOGRAPHIC {
+        // Parallel rays starting on the image plane through the camera
+        origin = vec3(pix_pos.xy, 0.0);
+        dir = vec3(0.0, 0.0, -1.0);
+    } else if camera.kind == CAMERA_BROWN_CONRADY {
+        dir = vec3(camera_undistort(camera, pix_pos.xy), -1.0);
+    }
+
+    if camera.lens_radius > 0.0 {
+        // Thin lens, all rays through a pixel converge on the plane of focus
+        let focus = origin + dir * (camera.focus_distance / -dir.z);
+        var lens = rand_disk(rand_state);
+        if camera.aperture_blades >= 3 {
+            lens = rand_polygon(rand_state, camera.aperture_blades, camera.aperture_rotation);
+        }
+        origin += vec3(camera.lens_radius * lens, 0.0);
+        dir = focus - origin;
+    }
+    return Ray((camera.transform * vec4(origin, 1.0)).xyz, (camera.transform * vec4(dir, 0.0)).xyz);
+}
+
+// Generates the ray of a 360° panorama through a position in the range [0, 1] on the image
+fn camera_panorama_ray(camera: Camera, uv: vec2f) -> Ray {
+    var pos = uv;
+    var eye = 0.0;
+    if camera.kind == CAMERA_STEREO_PANORAMA {
+        // The left eye sees the top half, the right eye the bottom half
+        eye = select(-0.5, 0.5, pos.y >= 0.5);
+        pos.y = fract(pos.y * 2.0);
+    }
+
+    // The center of the image looks down -Z, with longitude increasing towards +X
+    let phi = (pos.x - 0.5) * 2.0 * PI;
+    let theta = (0.5 - pos.y) * PI;
+    let dir = vec3(sin(phi) * cos(theta), sin(theta), -cos(phi) * cos(theta));
+    // Each eye sits on a circle, offset perpendicular to the horizontal viewing direction
+    let origin = eye * camera.interpupillary_distance * vec3(cos(phi), 0.0, sin(phi));
+    return Ray((camera.transform * vec4(origin, 1.0)).xyz, (camera.transform * vec4(dir, 0.0)).xyz);
+}
+
+// Generates the ray of a fisheye through a position relative to the image center, or a ray with a
+// zero direction outside of the image circle
+fn camera_fisheye_ray(camera: Camera, pos: vec2f) -> Ray {
+    let r = length(pos);
+    if r > 1.0 {
+        return Ray(vec3(0.0), vec3(0.0));
+    }
+
+    var theta: f32;
+    switch camera.kind {
+        case CAMERA_EQUISOLID: {
+            theta = 2.0 * asin(r * sin(camera.max_angle / 2.0));
+        }
+        case CAMERA_STEREOGRAPHIC: {
+            theta = 2.0 * atan(r * tan(camera.max_angle / 2.0));
+        }
+        default: {
+            theta = r * camera.max_angle;
+        }
+    }
+    let phi = atan2(pos.y, pos.x);
+    let dir = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), -cos(theta));
+    return Ray((camera.transform * vec4(0.0, 0.0, 0.0, 1.0)).xyz, (camera.transform * vec4(dir, 0.0)).xyz);
+}
+
+// Inverts the Brown-Conrady distortion of a position on the image plane by fixed point iteration.
+// The coefficients follow OpenCV, whose image coordinates point down.
+fn camera_undistort(camera: Camera, pos: vec2f) -> vec2f {
+    let distorted = vec2(pos.x, -pos.y);
+    let k = camera.radial_distortion;
+    let p = camera.tangential_distortion;
+
+    var undistorted = distorted;
+    for (var i = 0; i < 10; i++) {
+        let x = undistorted.x;
+        let y = undistorted.y;
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (k.x + r2 * (k.y + r2 * k.z));
+        let tangential = vec2(2.0 * p.x * x * y + p.y * (r2 + 2.0 * x * x), p.x * (r2 + 2.0 * y * y) + 2.0 * p.y * x * y);
+        undistorted = (distorted - tangential) / radial;
+    }
+    return vec2(undistorted.x, -undistorted.y);
+}
+
+// The cos⁴ falloff of natural vignetting for a camera ray
+fn camera_vignetting(camera: Camera, ray: Ray) -> f32 {
+    if camera.vignetting == 0u || camera.kind == CAMERA_EQUIRECTANGULAR || camera.kind == CAMERA_STEREO_PANORAMA {
+        return 1.0;
+    }
+    let forward = normalize((camera.transform * vec4(0.0, 0.0, -1.0, 0.0)).xyz);
+    let cos_theta = max(dot(normalize(ray.dir), forward), 0.0);
+    return cos_theta * cos_theta * cos_theta * cos_theta;
 }
\ No newline at end of file
@@ -0,0 +1,277 @@
+@group(1) @binding(3)
+var<storage, read> lights: array<Light>;
+
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+const LIGHT_RECT: u32 = 3u;
+const LIGHT_DISK: u32 = 4u;
+const LIGHT_SPHERE: u32 = 5u;
+const LIGHT_PORTAL: u32 = 6u;
+
+struct Light {
+    kind: u32,
+    intensity: vec3f,
+    position: vec3f,
+    direction: vec3f,
+    axis_u: vec3f,
+    axis_v: vec3f,
+    radius: f32,
+    range: f32,
+    angle_scale: f32,
+    angle_offset: f32,
+}
+
+struct LightSample {
+    // Direction from the shaded point towards the light
+    wi: vec3f,
+    distance: f32,
+    // Irradiance arriving at a surface facing the light, divided by the pdf of wi for area lights
+    irradiance: vec3f,
+}
+
+fn light_sample(light: Light, pos: vec3f, rand_state: ptr<function, u32>) -> LightSample {
+    switch light.kind {
+        case LIGHT_DIRECTIONAL: {
+            return LightSample(-light.direction, bitcast<f32>(0x7F800000), light.intensity);
+        }
+        case LIGHT_RECT: {
+            return light_sample_rect(light, pos, rand_state);
+        }
+        case LIGHT_DISK: {
+            return light_sample_disk(light, pos, rand_state);
+        }
+        case LIGHT_SPHERE: {
+            return light_sample_sphere(light, pos, rand_state);
+        }
+        case LIGHT_PORTAL: {
+            // Portals pass the environment through in both directions
+            var sample = sample_spherical_rect(light, pos, rand_state);
+            sample.irradiance *= scene_environment(sample.wi);
+            sample.distance = bitcast<f32>(0x7F800000);
+            return sample;
+        }
+        default: {
+            return light_sample_punctual(light, pos);
+        }
+    }
+}
+
+fn light_sample_punctual(light: Light, pos: vec3f) -> LightSample {
+    let to_light = light.position - pos;
+    let distance2 = max(dot(to_light, to_light), 0.0001);
+    let distance = sqrt(distance2);
+    let wi = to_light / distance;
+
+    var attenuation = 1.0 / distance2;
+    if light.range > 0.0 {
+        let ratio = distance / light.range;
+        attenuation *= clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    }
+    if light.kind == LIGHT_SPOT {
+        let t = clamp(dot(light.direction, -wi) * light.angle_scale + light.angle_offset, 0.0, 1.0);
+        attenuation *= t * t;
+    }
+
+    return LightSample(wi, distance, light.intensity * attenuation);
+}
+
+fn light_sample_rect(light: Light, pos: vec3f, rand_state: ptr<function, u32>) -> LightSample {
+    if dot(pos - light.position, light.direction) <= 0.0 {
+        return LightSample(light.direction, 0.0, vec3(0.0));
+    }
+
+    var sample = sample_spherical_rect(light, pos, rand_state);
+    sample.irradiance *= light.intensity;
+    return sample;
+}
+
+// Samples the solid angle subtended by a rectangle after Ureña et al., "An Area-Preserving
+// Parametrization for Spherical Rectangles". The irradiance is the solid angle, the inverse pdf.
+fn sample_spherical_rect(light: Light, pos: vec3f, rand_state: ptr<function, u32>) -> LightSample {
+    let exl = length(light.axis_u);
+    let eyl = length(light.axis_v);
+    let x = light.axis_u / exl;
+    let y = light.axis_v / eyl;
+    var z = cross(x, y);
+
+    let d = light.position - 0.5 * (light.axis_u + light.axis_v) - pos;
+    var z0 = dot(d, z);
+    if z0 > 0.0 {
+        z = -z;
+        z0 = -z0;
+    }
+    let x0 = dot(d, x);
+    let y0 = dot(d, y);
+    let x1 = x0 + exl;
+    let y1 = y0 + eyl;
+
+    let v00 = vec3(x0, y0, z0);
+    let v01 = vec3(x0, y1, z0);
+    let v10 = vec3(x1, y0, z0);
+    let v11 = vec3(x1, y1, z0);
+    let n0 = normalize(cross(v00, v10));
+    let n1 = normalize(cross(v10, v11));
+    let n2 = normalize(cross(v11, v01));
+    let n3 = normalize(cross(v01, v00));
+    let g0 = acos(clamp(-dot(n0, n1), -1.0, 1.0));
+    let g1 = acos(clamp(-dot(n1, n2), -1.0, 1.0));
+    let g2 = acos(clamp(-dot(n2, n3), -1.0, 1.0));
+    let g3 = acos(clamp(-dot(n3, n0), -1.0, 1.0));
+    let b0 = n0.z;
+    let b1 = n2.z;
+    let k = 2.0 * PI - g2 - g3;
+    let solid_angle = g0 + g1 - k;
+    if solid_angle <= 0.0 {
+        return LightSample(light.direction, 0.0, vec3(0.0));
+    }
+
+    let au = rand(rand_state) * solid_angle + k;
+    let fu = (cos(au) * b0 - b1) / sin(au);
+    let cu = clamp(sign(fu) / sqrt(fu * fu + b0 * b0), -1.0, 1.0);
+    let xu = clamp(-(cu * z0) / max(sqrt(1.0 - cu * cu), 1e-6), x0, x1);
+    let dist = sqrt(xu * xu + z0 * z0);
+    let h0 = y0 / sqrt(dist * dist + y0 * y0);
+    let h1 = y1 / sqrt(dist * dist + y1 * y1);
+    let hv = h0 + rand(rand_state) * (h1 - h0);
+    var yv = y1;
+    if hv * hv < 1.0 - 1e-6 {
+        yv = hv * dist / sqrt(1.0 - hv * hv);
+    }
+
+    let to_light = xu * x + yv * y + z0 * z;
+    let distance = length(to_light);
+    return LightSample(to_light / distance, distance, vec3(solid_angle));
+}
+
+// Samples a disk uniformly by area and converts the pdf to solid angle
+fn light_sample_disk(light: Light, pos: vec3f, rand_state: ptr<function, u32>) -> LightSample {
+    if dot(pos - light.position, light.direction) <= 0.0 {
+        return LightSample(light.direction, 0.0, vec3(0.0));
+    }
+
+    var a = vec3(1.0, 0.0, 0.0);
+    if abs(light.direction.x) > 0.9 {
+        a = vec3(0.0, 1.0, 0.0);
+    }
+    let u = normalize(cross(a, light.direction));
+    let v = cross(light.direction, u);
+    let offset = light.radius * rand_disk(rand_state);
+
+    let to_light = light.position + offset.x * u + offset.y * v - pos;
+    let distance2 = dot(to_light, to_light);
+    let distance = sqrt(distance2);
+    let wi = to_light / distance;
+    let cos_light = max(-dot(wi, light.direction), 0.0);
+    let area = PI * light.radius * light.radius;
+    return LightSample(wi, distance, light.intensity * area * cos_light / distance2);
+}
+
+// Samples the cone of directions subtended by a sphere
+fn light_sample_sphere(light: Light, pos: vec3f, rand_state: ptr<function, u32>) -> LightSample {
+    let to_center = light.position - pos;
+    let distance2 = dot(to_center, to_center);
+    let radius2 = light.radius * light.radius;
+    if distance2 <= radius2 {
+        return LightSample(light.direction, 0.0, vec3(0.0));
+    }
+
+    let cos_max = sqrt(1.0 - radius2 / distance2);
+    let wi = rand_cone(rand_state, to_center / sqrt(distance2), cos_max);
+    let b = dot(wi, to_center);
+    let distance = b - sqrt(max(b * b - distance2 + radius2, 0.0));
+    return LightSample(wi, distance, light.intensity * 2.0 * PI * (1.0 - cos_max));
+}
+
+// Returns the distance along a ray to an area light or portal, or a negative value if it's missed
+fn light_intersect(light: Light, ray: Ray) -> f32 {
+    switch light.kind {
+        case LIGHT_RECT, LIGHT_DISK, LIGHT_PORTAL: {
+            let denom = dot(ray.dir, light.direction);
+            if denom == 0.0 {
+                return -1.0;
+            }
+            let t = dot(light.position - ray.orig, light.direction) / denom;
+            let offset = ray_at(ray, t) - light.position;
+            if light.kind != LIGHT_DISK {
+                let u = dot(offset, light.axis_u) / dot(light.axis_u, light.axis_u);
+                let v = dot(offset, light.axis_v) / dot(light.axis_v, light.axis_v);
+                if abs(u) > 0.5 || abs(v) > 0.5 {
+                    return -1.0;
+                }
+            } else if dot(offset, offset) > light.radius * light.radius {
+                return -1.0;
+            }
+            return t;
+        }
+        case LIGHT_SPHERE: {
+            let oc = ray.orig - light.position;
+            let a = dot(ray.dir, ray.dir);
+            let b = dot(oc, ray.dir);
+            let c = dot(oc, oc) - light.radius * light.radius;
+            let discriminant = b * b - a * c;
+            if discriminant < 0.0 {
+                return -1.0;
+            }
+            let t = (-b - sqrt(discriminant)) / a;
+            if t < 0.001 {
+                return (-b + sqrt(discriminant)) / a;
+            }
+            return t;
+        }
+        default: {
+            return -1.0;
+        }
+    }
+}
+
+// Finds the closest area light along a ray before t_max, returning the radiance it emits towards
+// the ray origin
+fn lights_intersect(ray: Ray, t_max: f32, emission: ptr<function, vec3f>) -> bool {
+    var closest = t_max;
+    var hit = false;
+    for (var i = 0u; i < scene.num_lights; i++) {
+        let light = lights[i];
+        if light.kind == LIGHT_PORTAL {
+            continue;
+        }
+
+        let t = light_intersect(light, ray);
+        if t > 0.001 && t < closest {
+            closest = t;
+            hit = true;
+            *emission = light.intensity;
+            // Rectangles and disks only emit from their front side
+            if light.kind != LIGHT_SPHERE && dot(ray.dir, light.direction) > 0.0 {
+                *emission = vec3(0.0);
+            }
+        }
+    }
+    return hit;
+}
+
+// Whether an area light other than the one at index `skip` blocks a shadow ray before t_max. Area
+// lights are opaque from both sides.
+fn lights_occluded(ray: Ray, t_max: f32, skip: u32) -> bool {
+    for (var i = 0u; i < scene.num_lights; i++) {
+        if i == skip || lights[i].kind == LIGHT_PORTAL {
+            continue;
+        }
+        let t = light_intersect(lights[i], ray);
+        if t > 0.001 && t < t_max {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Whether a ray leaves through a portal, in which case lights_direct has already sampled the
+// environment it reaches
+fn portals_intersect(ray: Ray) -> bool {
+    for (var i = 0u; i < scene.num_lights; i++) {
+        if lights[i].kind == LIGHT_PORTAL && light_intersect(lights[i], ray) > 0.001 {
+            return true;
+        }
+    }
+    return false;
+}
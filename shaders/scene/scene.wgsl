@@ -7,9 +7,21 @@ var<storage, read> triangles: array<Triangle>;
 @group(1) @binding(2)
 var<storage, read> bvh_nodes: array<BvhNode>;
 
+const BACKGROUND_ENVIRONMENT: u32 = 0u;
+const BACKGROUND_COLOR: u32 = 1u;
+const BACKGROUND_IMAGE: u32 = 2u;
+const BACKGROUND_TRANSPARENT: u32 = 3u;
+
 struct SceneUniform {
     camera: Camera,
-    hdri: u32
+    hdri: u32,
+    hdri_rotation: mat3x3f,
+    hdri_intensity: f32,
+    background_mode: u32,
+    background: u32,
+    background_color: vec3f,
+    sky: Sky,
+    num_lights: u32,
 }
 
 struct Transform {
@@ -61,4 +73,45 @@ fn scene_intersect(ray: Ray, intersection: ptr<function, Intersection>) -> bool
     }
 
     return intersected;
-}
\ No newline at end of file
+}
+
+// Radiance arriving from the environment map or the sky along a normalized direction, excluding
+// the sun disk which is sampled explicitly
+fn scene_environment(dir: vec3f) -> vec3f {
+    if scene.sky.enabled != 0u {
+        return scene.hdri_intensity * sky_radiance(scene.sky, dir);
+    }
+    return scene.hdri_intensity * sample_panorama(scene.hdri, scene.hdri_rotation * dir);
+}
+
+// Radiance of the sun disk of the sky along a normalized direction
+fn scene_sun(dir: vec3f) -> vec3f {
+    if scene.sky.enabled != 0u {
+        return scene.hdri_intensity * sky_sun_radiance(scene.sky, dir);
+    }
+    return vec3(0.0);
+}
+
+fn scene_occluded(ray: Ray, t_max: f32) -> bool {
+    var intersection = Intersection();
+    return scene_intersect(ray, &intersection) && intersection.t < t_max;
+}
+
+// What a camera ray escaping the scene sees, with alpha 0 for a transparent background
+fn scene_background(dir: vec3f) -> vec4f {
+    switch scene.background_mode {
+        case BACKGROUND_COLOR: {
+            return vec4(scene.background_color, 1.0);
+        }
+        case BACKGROUND_IMAGE: {
+            let color = sample_panorama(scene.background, scene.hdri_rotation * dir);
+            return vec4(scene.hdri_intensity * color, 1.0);
+        }
+        case BACKGROUND_TRANSPARENT: {
+            return vec4(0.0, 0.0, 0.0, 0.0);
+        }
+        default: {
+            return vec4(scene_environment(dir) + scene_sun(dir), 1.0);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+struct Sky {
+    enabled: u32,
+    perez: array<vec3f, 5>,
+    zenith: vec3f,
+    sun_dir: vec3f,
+    sun_radiance: vec3f,
+    sun_cos_radius: f32,
+}
+
+// Radiance of the Preetham sky without the sun disk
+fn sky_radiance(sky: Sky, dir: vec3f) -> vec3f {
+    let cos_theta = max(dir.y, 0.01);
+    let cos_gamma = clamp(dot(dir, sky.sun_dir), -1.0, 1.0);
+    let gamma = acos(cos_gamma);
+
+    let perez = (1.0 + sky.perez[0] * exp(sky.perez[1] / cos_theta)) *
+                (1.0 + sky.perez[2] * exp(sky.perez[3] * gamma) + sky.perez[4] * cos_gamma * cos_gamma);
+    let luminance_xy = sky.zenith * perez;
+
+    // xyY to XYZ to linear sRGB
+    let y = luminance_xy.x;
+    let xyz = vec3(luminance_xy.y / luminance_xy.z * y, y, (1.0 - luminance_xy.y - luminance_xy.z) / luminance_xy.z * y);
+    let rgb = mat3x3f(
+        3.2406, -0.9689, 0.0557,
+        -1.5372, 1.8758, -0.2040,
+        -0.4986, 0.0415, 1.0570,
+    ) * xyz;
+    return max(rgb, vec3(0.0));
+}
+
+// Radiance of the sun disk, which is zero outside of it
+fn sky_sun_radiance(sky: Sky, dir: vec3f) -> vec3f {
+    if dot(dir, sky.sun_dir) >= sky.sun_cos_radius {
+        return sky.sun_radiance;
+    }
+    return vec3(0.0);
+}
+
+// Samples a direction uniformly within the cone of the sun disk
+fn sky_sample_sun(sky: Sky, state: ptr<function, u32>) -> vec3f {
+    return rand_cone(state, sky.sun_dir, sky.sun_cos_radius);
+}
+
+// Solid angle of the sun disk, the inverse of the pdf of sky_sample_sun
+fn sky_sun_solid_angle(sky: Sky) -> f32 {
+    return 2.0 * PI * (1.0 - sky.sun_cos_radius);
+}
@@ -1 +1,2 @@
-const PI: f32 = 3.14159;
\ No newline at end of file
+const PI: f32 = 3.14159;
+const NO_TEXTURE: u32 = 0xFFFFFFFFu;
\ No newline at end of file
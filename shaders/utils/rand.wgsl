@@ -18,6 +18,21 @@ fn rand_disk(state: ptr<function, u32>) -> vec2f {
     return r * vec2(cos(theta), sin(theta));
 }
 
+// Samples a point uniformly within a regular polygon inscribed in the unit circle
+fn rand_polygon(state: ptr<function, u32>, sides: u32, rotation: f32) -> vec2f {
+    let side = min(u32(rand(state) * f32(sides)), sides - 1u);
+    let angle = 2.0 * PI / f32(sides);
+    let a = rotation + f32(side) * angle;
+
+    var u = rand(state);
+    var v = rand(state);
+    if u + v > 1.0 {
+        u = 1.0 - u;
+        v = 1.0 - v;
+    }
+    return u * vec2(cos(a), sin(a)) + v * vec2(cos(a + angle), sin(a + angle));
+}
+
 fn rand_sphere(state: ptr<function, u32>) -> vec3f {
     let a = rand(state);
     let b = rand(state);
@@ -27,6 +42,21 @@ fn rand_sphere(state: ptr<function, u32>) -> vec3f {
     return vec3(x, y, z);
 }
 
+// Samples a direction uniformly within the cone around a normalized axis
+fn rand_cone(state: ptr<function, u32>, axis: vec3f, cos_max: f32) -> vec3f {
+    let cos_theta = 1.0 - rand(state) * (1.0 - cos_max);
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = 2.0 * PI * rand(state);
+
+    var a = vec3(1.0, 0.0, 0.0);
+    if abs(axis.x) > 0.9 {
+        a = vec3(0.0, 1.0, 0.0);
+    }
+    let u = normalize(cross(a, axis));
+    let v = cross(axis, u);
+    return normalize(sin_theta * cos(phi) * u + sin_theta * sin(phi) * v + cos_theta * axis);
+}
+
 fn jenkins_hash(x: u32) -> u32 {
     var res = x + x << 10;
     res ^= res >> 6;
@@ -0,0 +1,126 @@
+const TONE_MAP_LINEAR: u32 = 0u;
+const TONE_MAP_REINHARD: u32 = 1u;
+const TONE_MAP_ACES: u32 = 2u;
+const TONE_MAP_AGX: u32 = 3u;
+const TONE_MAP_NEUTRAL: u32 = 4u;
+
+// Maps linear radiance to the [0, 1] range with an operator `op`, where a positive white point is the
+// radiance mapped to white
+fn tone_map(color: vec3f, op: u32, white_point: f32) -> vec3f {
+    let white = vec3(white_point);
+    switch op {
+        case TONE_MAP_LINEAR: {
+            if white_point > 0.0 {
+                return saturate(color / white);
+            }
+            return saturate(color);
+        }
+        case TONE_MAP_ACES: {
+            return tone_map_normalized(tone_map_aces(color), tone_map_aces(white), white_point);
+        }
+        case TONE_MAP_AGX: {
+            return tone_map_normalized(tone_map_agx(color), tone_map_agx(white), white_point);
+        }
+        case TONE_MAP_NEUTRAL: {
+            return tone_map_normalized(tone_map_neutral(color), tone_map_neutral(white), white_point);
+        }
+        default: {
+            return tone_map_reinhard(color, white_point);
+        }
+    }
+}
+
+fn tone_map_normalized(color: vec3f, white: vec3f, white_point: f32) -> vec3f {
+    if white_point > 0.0 {
+        return saturate(color / white);
+    }
+    return saturate(color);
+}
+
+// Extended Reinhard, which reduces to color / (color + 1) for an infinite white point
+fn tone_map_reinhard(color: vec3f, white_point: f32) -> vec3f {
+    var numerator = color;
+    if white_point > 0.0 {
+        numerator *= vec3(1.0) + color / (white_point * white_point);
+    }
+    return saturate(numerator / (color + vec3(1.0)));
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+fn tone_map_aces(color: vec3f) -> vec3f {
+    // sRGB to ACEScg with the RRT saturation, rows given as columns to be multiplied from the left
+    let input = mat3x3f(
+        0.59719, 0.35458, 0.04823,
+        0.07600, 0.90834, 0.01566,
+        0.02840, 0.13383, 0.83777,
+    );
+    let output = mat3x3f(
+        1.60475, -0.53108, -0.07367,
+        -0.10208, 1.10813, -0.00605,
+        -0.00327, -0.07276, 1.07602,
+    );
+
+    let v = color * input;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return saturate((a / b) * output);
+}
+
+// Troy Sobotka's AgX with the polynomial fit of its default contrast curve
+fn tone_map_agx(color: vec3f) -> vec3f {
+    let inset = mat3x3f(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset = mat3x3f(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var v = inset * color;
+    v = clamp(log2(max(v, vec3(1e-10))), vec3(min_ev), vec3(max_ev));
+    v = (v - min_ev) / (max_ev - min_ev);
+
+    let v2 = v * v;
+    let v4 = v2 * v2;
+    v = 15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v - 0.00232;
+
+    // The curve produces display encoded values, decode them to apply the output transfer function
+    v = outset * v;
+    return saturate(pow(max(v, vec3(0.0)), vec3(2.2)));
+}
+
+// The Khronos PBR Neutral tone mapper, which keeps base colors of materials accurate
+fn tone_map_neutral(color: vec3f) -> vec3f {
+    let start_compression = 0.8 - 0.04;
+    let desaturation = 0.15;
+
+    let x = min(color.r, min(color.g, color.b));
+    var offset = 0.04;
+    if x < 0.08 {
+        offset = x - 6.25 * x * x;
+    }
+    var v = color - offset;
+
+    let peak = max(v.r, max(v.g, v.b));
+    if peak < start_compression {
+        return saturate(v);
+    }
+
+    let d = 1.0 - start_compression;
+    let new_peak = 1.0 - d * d / (peak + d - start_compression);
+    v *= new_peak / peak;
+    let g = 1.0 - 1.0 / (desaturation * (peak - new_peak) + 1.0);
+    return saturate(mix(v, vec3(new_peak), g));
+}
+
+// The sRGB transfer function encoding linear values for display
+fn srgb_oetf(color: vec3f) -> vec3f {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3(0.0031308));
+}
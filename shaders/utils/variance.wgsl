@@ -0,0 +1,11 @@
+// The standard error of the mean luminance of a pixel relative to the mean, from the sums of the
+// luminance and its square over n > 1 samples
+fn variance_relative_error(sum: f32, sum_squares: f32, n: f32) -> f32 {
+    let mean = sum / n;
+    let sample_variance = max(sum_squares - sum * mean, 0.0) / (n - 1.0);
+    let error = sqrt(sample_variance / n);
+    if error == 0.0 {
+        return 0.0;
+    }
+    return error / mean;
+}
@@ -1,7 +1,8 @@
 use std::fmt::{self, Display};
 use std::str::FromStr;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use glam::Vec3;
 
 #[derive(Parser)]
 #[command(version)]
@@ -10,22 +11,229 @@ pub struct Config {
     #[arg(short, long, default_value_t = Size::new(400, 225))]
     pub size: Size,
 
-    /// The path to the output
+    /// The path to the output, whose extension chooses the format. OpenEXR (`.exr`), Radiance
+    /// HDR (`.hdr`) and PFM (`.pfm`) files hold linear radiance instead of the tone mapped image
     #[arg(short, long, default_value_t = String::from("output.png"))]
     pub output: String,
 
+    /// Write OpenEXR output with 16 bit instead of 32 bit floats
+    #[arg(long)]
+    pub half: bool,
+
+    /// The bits per channel of PNG and TIFF output, either 8 or 16
+    #[arg(long, default_value_t = 8, value_parser = parse_bit_depth)]
+    pub bit_depth: u32,
+
+    /// The noise added to the image when quantizing it to 8 bits, which prevents banding
+    #[arg(long, value_enum, default_value_t = Dither::None)]
+    pub dither: Dither,
+
+    /// Auxiliary outputs of the first hits, written as layers of OpenEXR output or as extra
+    /// OpenEXR files next to other output
+    #[arg(long = "aov", value_enum, value_delimiter = ',')]
+    pub aovs: Vec<Aov>,
+
     /// The maximum depth of each camera ray
     #[arg(long, default_value_t = 50)]
     pub depth: u32,
 
-    /// The number of samples per pixel
+    /// The number of samples per pixel, on average when sampling adaptively
     #[arg(long, default_value_t = 1000)]
     pub samples: u32,
 
-    /// The environment map of the scene to be rendered
+    /// The relative standard error of the mean luminance below which pixels stop being sampled,
+    /// spending their samples on noisier pixels instead
+    #[arg(long)]
+    pub noise_threshold: Option<f32>,
+
+    /// The number of seconds after which rendering stops, replacing the number of samples
+    #[arg(long)]
+    pub time_limit: Option<f32>,
+
+    /// The relative standard error of the mean luminance of every pixel at which rendering stops,
+    /// replacing the number of samples, which then only caps the samples of a pixel at 8 times it.
+    /// Pixels below it stop being sampled, like with a noise threshold of the same value
+    #[arg(long, conflicts_with = "noise_threshold")]
+    pub target_noise: Option<f32>,
+
+    /// The path of an image showing the number of samples of each pixel
+    #[arg(long)]
+    pub heatmap: Option<String>,
+
+    /// The environment map of the scene to be rendered, either a lat-long panorama, a cube map
+    /// cross or six comma-separated cube map faces
     #[arg(long, default_value_t = String::from("textures/indoor.hdr"))]
     pub hdri: String,
 
+    /// The rotation of the environment map around the vertical axis, in degrees
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub hdri_yaw: f32,
+
+    /// The tilt of the environment map around the horizontal axis, in degrees
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub hdri_pitch: f32,
+
+    /// The exposure adjustment of the environment map, in stops
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub hdri_exposure: f32,
+
+    /// What camera rays see instead of the environment map: `environment`, `transparent`, a linear
+    /// `r,g,b` color or the path to another environment image
+    #[arg(long, default_value_t = Background::Environment)]
+    pub background: Background,
+
+    /// Light the scene with a procedural Preetham sky and sun instead of the environment map, which
+    /// is then not loaded
+    #[arg(long)]
+    pub sky: bool,
+
+    /// The atmospheric turbidity of the sky, from 1.7 for a very clear to 10 for a hazy sky
+    #[arg(long, default_value_t = 3.0)]
+    pub turbidity: f32,
+
+    /// The elevation of the sun above the horizon, in degrees
+    #[arg(long, default_value_t = 45.0, allow_negative_numbers = true)]
+    pub sun_elevation: f32,
+
+    /// The azimuth of the sun, in degrees clockwise from north (-Z) towards east (+X)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub sun_azimuth: f32,
+
+    /// The latitude of the scene in degrees, places the sun together with the longitude and time
+    #[arg(long, allow_negative_numbers = true, requires_all = ["longitude", "date_time"])]
+    pub latitude: Option<f64>,
+
+    /// The longitude of the scene in degrees, positive towards east
+    #[arg(long, allow_negative_numbers = true, requires_all = ["latitude", "date_time"])]
+    pub longitude: Option<f64>,
+
+    /// The UTC date and time to place the sun at, formatted like `2024-06-21T12:00`
+    #[arg(long, requires_all = ["latitude", "longitude"])]
+    pub date_time: Option<String>,
+
+    /// A JSON file of rectangle, disk and sphere area lights to add to the scene
+    #[arg(long)]
+    pub lights: Option<String>,
+
+    /// The name or index of the glTF camera to render from, the first camera by default
+    #[arg(long)]
+    pub camera: Option<String>,
+
+    /// Moves the camera to a position `x,y,z`, keeping its orientation unless it is given a target
+    #[arg(long, value_parser = parse_vec3, allow_negative_numbers = true)]
+    pub camera_position: Option<Vec3>,
+
+    /// Points the camera at a target `x,y,z`
+    #[arg(long, value_parser = parse_vec3, allow_negative_numbers = true)]
+    pub look_at: Option<Vec3>,
+
+    /// The up vector `x,y,z` of a camera pointed at a target, +Y by default
+    #[arg(long, value_parser = parse_vec3, allow_negative_numbers = true)]
+    pub camera_up: Option<Vec3>,
+
+    /// The vertical field of view of the camera, in degrees
+    #[arg(long)]
+    pub vfov: Option<f32>,
+
+    /// The aspect ratio of the camera, the one of the image size by default
+    #[arg(long)]
+    pub aspect_ratio: Option<f32>,
+
+    /// Replaces the projection of the camera with a 360° panorama or a fisheye lens
+    #[arg(long)]
+    pub projection: Option<Projection>,
+
+    /// The field of view of fisheye projections across the shorter side of the image, in degrees
+    #[arg(long, default_value_t = 180.0)]
+    pub fisheye_fov: f32,
+
+    /// Brown-Conrady distortion coefficients `k1,k2,p1,p2,k3` of a perspective camera, in the
+    /// order used by OpenCV, with omitted coefficients being zero
+    #[arg(long, value_delimiter = ',', num_args = 1..=5, allow_negative_numbers = true)]
+    pub distortion: Vec<f32>,
+
+    /// The interpupillary distance of stereo panoramas, in scene units
+    #[arg(long, default_value_t = 0.064)]
+    pub ipd: f32,
+
+    /// The f-number of the camera aperture, assuming a full frame sensor and a scene in meters.
+    /// Without a focus distance it only sets the exposure, otherwise it enables depth of field
+    #[arg(long, conflicts_with = "aperture_radius", value_parser = parse_positive)]
+    pub f_stop: Option<f32>,
+
+    /// The radius of the camera aperture for depth of field, in scene units
+    #[arg(long)]
+    pub aperture_radius: Option<f32>,
+
+    /// The distance from the camera that is in focus, in scene units
+    #[arg(long)]
+    pub focus_distance: Option<f32>,
+
+    /// The number of blades shaping the aperture into a polygon, giving polygonal bokeh
+    #[arg(long)]
+    pub aperture_blades: Option<u32>,
+
+    /// The rotation of a polygonal aperture, in degrees
+    #[arg(long, allow_negative_numbers = true)]
+    pub aperture_rotation: Option<f32>,
+
+    /// The exposure value at ISO 100, replacing the ISO sensitivity and shutter time
+    #[arg(long, allow_negative_numbers = true, conflicts_with_all = ["iso", "shutter"])]
+    pub ev100: Option<f32>,
+
+    /// The ISO sensitivity of the camera, exposing the image together with the shutter time and
+    /// the f-stop
+    #[arg(long)]
+    pub iso: Option<f32>,
+
+    /// The shutter time of the camera in seconds, like `0.008` or `1/125`
+    #[arg(long, value_parser = parse_seconds)]
+    pub shutter: Option<f32>,
+
+    /// Darken the image towards its edges by natural vignetting
+    #[arg(long)]
+    pub vignetting: bool,
+
+    /// Filter the noise of the radiance before tone mapping, guided by the albedo, normal and
+    /// depth of the first hits
+    #[arg(long)]
+    pub denoise: bool,
+
+    /// The tone mapping operator applied to the image
+    #[arg(long, value_enum, default_value_t = ToneMap::Reinhard)]
+    pub tone_map: ToneMap,
+
+    /// Expose the image automatically for the average luminance of a histogram of the image,
+    /// replacing the exposure of the camera
+    #[arg(long)]
+    pub auto_exposure: bool,
+
+    /// The percentage of darkest pixels ignored by automatic exposure
+    #[arg(long, default_value_t = 50.0)]
+    pub auto_exposure_low: f32,
+
+    /// The percentage of pixels below which pixels are averaged by automatic exposure, ignoring
+    /// the brightest ones
+    #[arg(long, default_value_t = 95.0)]
+    pub auto_exposure_high: f32,
+
+    /// The exposure adjustment of the image before tone mapping, in stops, which compensates
+    /// automatic exposure
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub exposure: f32,
+
+    /// The exposed radiance mapped to white by the tone mapping operator
+    #[arg(long)]
+    pub white_point: Option<f32>,
+
+    /// The maximum width or height of loaded textures, larger images are downscaled
+    #[arg(long, default_value_t = 4096)]
+    pub max_texture_size: u32,
+
+    /// Attenuate indirect diffuse lighting with the occlusion textures of materials
+    #[arg(long)]
+    pub occlusion: bool,
+
     /// The path to the glTF scene description file
     pub scene: String,
 }
@@ -61,3 +269,135 @@ impl Display for Size {
         write!(f, "{}x{}", self.width, self.height)
     }
 }
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Projection {
+    /// A mono lat-long panorama
+    Equirectangular,
+    /// An omni-directional stereo panorama, with the left eye on top of the right eye
+    Ods,
+    /// A fisheye whose distance from the center is proportional to the angle
+    Equidistant,
+    /// A fisheye preserving solid angles
+    Equisolid,
+    /// A fisheye preserving angles
+    Stereographic,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ToneMap {
+    /// Clamps the image
+    Linear,
+    /// Reinhard's operator, extended with a white point
+    Reinhard,
+    /// Stephen Hill's fit of ACES
+    Aces,
+    /// Troy Sobotka's AgX
+    Agx,
+    /// The Khronos PBR Neutral operator
+    Neutral,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Dither {
+    None,
+    /// White noise with a triangular distribution
+    Triangular,
+    /// A tiled blue noise mask, which is less visible than white noise
+    BlueNoise,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Aov {
+    /// The base color of the material
+    Albedo,
+    /// The shading normal in world space, including normal maps
+    Normal,
+    /// The position in world space
+    Position,
+    /// The distance from the camera
+    Depth,
+    /// The texture coordinates
+    Uv,
+    /// The index of the material
+    Material,
+    /// The index of the mesh primitive
+    Primitive,
+}
+
+fn parse_bit_depth(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(bit_depth @ (8 | 16)) => Ok(bit_depth),
+        _ => Err(String::from("the bit depth must be 8 or 16")),
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let components: Vec<_> = s.split(',').map(str::parse::<f32>).collect();
+    match components[..] {
+        [Ok(x), Ok(y), Ok(z)] => Ok(Vec3::new(x, y, z)),
+        _ => Err(String::from("expected three comma-separated numbers")),
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(String::from("the value must be positive"))
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<f32, String> {
+    let seconds = match s.split_once('/') {
+        Some((numerator, denominator)) => {
+            let numerator: f32 = numerator.parse().map_err(|e| format!("{e}"))?;
+            let denominator: f32 = denominator.parse().map_err(|e| format!("{e}"))?;
+            numerator / denominator
+        }
+        None => s.parse().map_err(|e| format!("{e}"))?,
+    };
+    if seconds > 0.0 && seconds.is_finite() {
+        Ok(seconds)
+    } else {
+        Err(String::from("the time must be positive"))
+    }
+}
+
+#[derive(Clone)]
+pub enum Background {
+    Environment,
+    Transparent,
+    Color(Vec3),
+    Image(String),
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "environment" => return Ok(Self::Environment),
+            "transparent" => return Ok(Self::Transparent),
+            _ => {}
+        }
+
+        let components: Vec<_> = s.split(',').map(str::parse::<f32>).collect();
+        match components[..] {
+            [Ok(r), Ok(g), Ok(b)] => Ok(Self::Color(Vec3::new(r, g, b))),
+            _ => Ok(Self::Image(String::from(s))),
+        }
+    }
+}
+
+impl Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Environment => write!(f, "environment"),
+            Self::Transparent => write!(f, "transparent"),
+            Self::Color(color) => write!(f, "{},{},{}", color.x, color.y, color.z),
+            Self::Image(path) => write!(f, "{path}"),
+        }
+    }
+}
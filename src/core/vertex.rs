@@ -1,9 +1,11 @@
 use encase::ShaderType;
-use glam::{Vec2, Vec3};
+use glam::{Vec2, Vec3, Vec4};
 
 #[derive(ShaderType, Copy, Clone)]
 pub struct Vertex {
     pub pos: Vec3,
     pub normal: Vec3,
+    /// The tangent in `xyz` and the handedness of the bitangent in `w`, as in glTF
+    pub tangent: Vec4,
     pub tex_coord: Vec2,
 }
@@ -1,6 +1,7 @@
 pub mod config;
 pub mod core;
 pub mod materials;
+pub mod output;
 pub mod primitives;
 pub mod render;
 pub mod scene;
@@ -1,20 +1,30 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::HashSet, rc::Rc};
 
 use encase::{ShaderType, StorageBuffer};
 
 use crate::render::RenderContext;
 
+/// Marks an optional texture slot of a [`Material`] as unused
+pub const NO_TEXTURE: u32 = u32::MAX;
+
 #[derive(ShaderType)]
 pub struct Material {
     base_color_texture: u32,
     metallic_roughness_texture: u32,
     normal_texture: u32,
     emissive_texture: u32,
+    occlusion_texture: u32,
+    normal_scale: f32,
+    occlusion_strength: f32,
 }
 
 #[derive(Default)]
 pub struct Materials {
     registry: Vec<Material>,
+    /// The images holding linear data rather than sRGB colors
+    linear_images: HashSet<u32>,
+    /// The images holding base colors, which are decoded from sRGB
+    color_images: HashSet<u32>,
 }
 
 impl Materials {
@@ -24,28 +34,46 @@ impl Materials {
 
     pub fn add(&mut self, material: &gltf::Material) -> Option<u32> {
         let pbr_metallic_roughness = material.pbr_metallic_roughness();
-        let base_color_texture = pbr_metallic_roughness
-            .base_color_texture()?
-            .texture()
-            .source()
-            .index() as u32;
-        let metallic_roughness_texture = pbr_metallic_roughness
-            .metallic_roughness_texture()?
-            .texture()
-            .source()
-            .index() as u32;
-        let normal_texture = material.normal_texture()?.texture().source().index() as u32;
+        let base_color_texture =
+            texture_source(&pbr_metallic_roughness.base_color_texture()?.texture())?;
+        let metallic_roughness_texture = texture_source(
+            &pbr_metallic_roughness
+                .metallic_roughness_texture()?
+                .texture(),
+        )?;
+        let normal_texture = material.normal_texture()?;
         //        let emissive_texture = material.emissive_texture()?.texture().source().index() as u32;
+        let occlusion_texture = material.occlusion_texture();
+
+        let occlusion_texture_source = occlusion_texture
+            .as_ref()
+            .and_then(|texture| texture_source(&texture.texture()));
+        let normal_texture_source = texture_source(&normal_texture.texture())?;
+        self.color_images.insert(base_color_texture);
+        self.linear_images.extend(
+            [metallic_roughness_texture, normal_texture_source]
+                .into_iter()
+                .chain(occlusion_texture_source),
+        );
 
         self.registry.push(Material {
             base_color_texture,
             metallic_roughness_texture,
-            normal_texture,
+            normal_texture: normal_texture_source,
             emissive_texture: 0, // emissive_texture,
+            occlusion_texture: occlusion_texture_source.unwrap_or(NO_TEXTURE),
+            normal_scale: normal_texture.scale(),
+            occlusion_strength: occlusion_texture.map_or(1.0, |texture| texture.strength()),
         });
         Some(self.registry.len() as u32 - 1)
     }
 
+    /// Whether an image holds linear data, i.e. it is only used by the metallic-roughness, normal
+    /// or occlusion slots of the materials added so far
+    pub fn is_linear(&self, image: u32) -> bool {
+        self.linear_images.contains(&image) && !self.color_images.contains(&image)
+    }
+
     pub fn build(
         &self,
         context: Rc<RefCell<RenderContext>>,
@@ -92,3 +120,15 @@ impl Materials {
         Ok((bind_group_layout, bind_group))
     }
 }
+
+/// Returns the image index of `texture`, preferring the KTX2 image of `KHR_texture_basisu` over the
+/// fallback source, which is optional when the extension is present.
+fn texture_source(texture: &gltf::Texture) -> Option<u32> {
+    match texture
+        .extension_value("KHR_texture_basisu")
+        .and_then(|basisu| basisu.get("source")?.as_u64())
+    {
+        Some(index) => Some(index as u32),
+        None => texture.source().map(|image| image.index() as u32),
+    }
+}
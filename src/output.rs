@@ -0,0 +1,205 @@
+//! Writing of the rendered image in a format chosen by the extension of the output path.
+//!
+//! OpenEXR, Radiance HDR and PFM files hold the linear radiance of the render target, while other
+//! formats hold the tone mapped image of the post processor, with 16 bits per channel for PNG and
+//! TIFF files if requested. AOVs, and the unfiltered radiance when denoising, are written as layers
+//! of OpenEXR output, or as OpenEXR files next to other output. A heatmap of the number of samples
+//! of each pixel can be written to a separate image.
+use crate::{
+    config::{Aov, Config},
+    render::PostProcessor,
+    textures::encode_pfm,
+};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use exr::prelude::{f16, AnyChannel, AnyChannels, FlatSamples, Image, SmallVec, WritableImage};
+use image::{codecs::hdr::HdrEncoder, Rgb, RgbImage, Rgba32FImage};
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// Where and how the rendered image is saved
+pub struct Output {
+    path: String,
+    /// Write OpenEXR files with half floats
+    half: bool,
+    /// The bits per channel of PNG and TIFF files
+    bit_depth: u32,
+    aovs: Vec<Aov>,
+    /// Also write the unfiltered radiance
+    denoise: bool,
+    heatmap: Option<String>,
+}
+
+impl Output {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            path: config.output.clone(),
+            half: config.half,
+            bit_depth: config.bit_depth,
+            aovs: config.aovs.clone(),
+            denoise: config.denoise,
+            heatmap: config.heatmap.clone(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Saves the image of the post processor, together with the AOVs and the unfiltered radiance
+    pub async fn save(&self, post_processor: &PostProcessor) -> Result<()> {
+        let path = self.path.as_str();
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+
+        // The extra layers with their names and the names of their channels
+        let mut layers = Vec::new();
+        for &aov in &self.aovs {
+            let name = aov_name(aov);
+            if !layers.iter().any(|(saved, _, _)| *saved == name) {
+                let image = post_processor.retrieve_aov(aov).await?;
+                layers.push((name, aov_channels(aov), image));
+            }
+        }
+        if self.denoise {
+            let image = post_processor.retrieve_unfiltered_radiance().await?;
+            layers.push((String::from("unfiltered"), RGB_CHANNELS, image));
+        }
+
+        if extension.as_deref() == Some("exr") {
+            let radiance = post_processor.retrieve_radiance().await?;
+            let mut channels = image_channels("", RGB_CHANNELS, &radiance);
+            for (name, names, image) in &layers {
+                channels.extend(image_channels(&format!("{name}."), names, image));
+            }
+            write_exr(path, &radiance, channels, self.half)?;
+            return self.save_heatmap(post_processor).await;
+        }
+
+        match extension.as_deref() {
+            Some("hdr") => write_hdr(path, &post_processor.retrieve_radiance().await?)?,
+            Some("pfm") => {
+                std::fs::write(path, encode_pfm(&post_processor.retrieve_radiance().await?))?
+            }
+            Some("png" | "tif" | "tiff") if self.bit_depth == 16 => {
+                post_processor.retrieve_result_16().await?.save(path)?
+            }
+            _ => {
+                let image = post_processor
+                    .retrieve_result()
+                    .await?
+                    .ok_or_else(|| anyhow!("Failed to read back the image"))?;
+                image.save(path)?
+            }
+        }
+
+        for (name, names, image) in &layers {
+            let layer_path = Path::new(path).with_extension(format!("{name}.exr"));
+            let channels = image_channels("", names, image);
+            write_exr(layer_path, image, channels, self.half)?;
+        }
+        self.save_heatmap(post_processor).await
+    }
+
+    /// Saves the number of samples of each pixel relative to the maximum, from blue to red
+    async fn save_heatmap(&self, post_processor: &PostProcessor) -> Result<()> {
+        let Some(path) = &self.heatmap else {
+            return Ok(());
+        };
+
+        let sample_counts = post_processor.retrieve_sample_counts().await?;
+        let (width, height) = post_processor.size();
+        let max_samples = sample_counts.iter().copied().fold(1.0, f32::max);
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            let samples = sample_counts[(y * width + x) as usize];
+            heatmap_color(samples / max_samples)
+        });
+        Ok(image.save(path)?)
+    }
+}
+
+/// Maps a value in [0, 1] to blue, cyan, green, yellow and red
+fn heatmap_color(value: f32) -> Rgb<u8> {
+    const STOPS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+    ];
+    let x = value.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let i = (x as usize).min(STOPS.len() - 2);
+    let t = x - i as f32;
+    Rgb(std::array::from_fn(|c| {
+        let channel = STOPS[i][c] + t * (STOPS[i + 1][c] - STOPS[i][c]);
+        (channel * 255.0).round() as u8
+    }))
+}
+
+const RGB_CHANNELS: &[&str] = &["R", "G", "B"];
+
+/// The name of an AOV, as given on the command line
+fn aov_name(aov: Aov) -> String {
+    aov.to_possible_value().unwrap().get_name().to_owned()
+}
+
+/// The names of the channels holding the components of an AOV, followed by its coverage
+fn aov_channels(aov: Aov) -> &'static [&'static str] {
+    match aov {
+        Aov::Albedo => RGB_CHANNELS,
+        Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+        Aov::Depth => &["Z"],
+        Aov::Uv => &["U", "V"],
+        Aov::Material | Aov::Primitive => &["id"],
+    }
+}
+
+/// Splits the leading components of an image into named channels, adding alpha as `A`
+fn image_channels(prefix: &str, names: &[&str], image: &Rgba32FImage) -> Vec<(String, Vec<f32>)> {
+    let channel = |name: &str, component: usize| {
+        let values = image.pixels().map(|pixel| pixel[component]).collect();
+        (format!("{prefix}{name}"), values)
+    };
+    let mut channels: Vec<_> = names
+        .iter()
+        .enumerate()
+        .map(|(component, name)| channel(name, component))
+        .collect();
+    channels.push(channel("A", 3));
+    channels
+}
+
+fn write_exr(
+    path: impl AsRef<Path>,
+    image: &Rgba32FImage,
+    channels: Vec<(String, Vec<f32>)>,
+    half: bool,
+) -> Result<()> {
+    let channels = channels
+        .into_iter()
+        .map(|(name, values)| {
+            let samples = if half {
+                FlatSamples::F16(values.into_iter().map(f16::from_f32).collect())
+            } else {
+                FlatSamples::F32(values)
+            };
+            AnyChannel::new(name.as_str(), samples)
+        })
+        .collect();
+    let size = (image.width() as usize, image.height() as usize);
+    Image::from_channels(size, AnyChannels::sort(SmallVec::from_vec(channels)))
+        .write()
+        .to_file(path)?;
+    Ok(())
+}
+
+fn write_hdr(path: &str, image: &Rgba32FImage) -> Result<()> {
+    let pixels: Vec<_> = image
+        .pixels()
+        .map(|pixel| Rgb([pixel[0], pixel[1], pixel[2]]))
+        .collect();
+    let writer = BufWriter::new(File::create(path)?);
+    HdrEncoder::new(writer).encode(&pixels, image.width() as usize, image.height() as usize)?;
+    Ok(())
+}
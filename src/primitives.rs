@@ -1,8 +1,10 @@
 use std::{cell::RefCell, rc::Rc};
 
+use bevy_mikktspace::Geometry;
 use encase::StorageBuffer;
-use glam::{Vec2, Vec3};
+use glam::{Vec2, Vec3, Vec4};
 use gltf::{buffer::Data, Primitive};
+use log::warn;
 
 use crate::{
     core::{Triangle, Vertex},
@@ -39,8 +41,20 @@ impl Primitives {
         let positions: Vec<_> = reader.read_positions()?.collect();
         let normals: Vec<_> = reader.read_normals()?.collect();
         let tex_coords: Vec<_> = reader.read_tex_coords(0)?.into_f32().collect();
+        let tangents: Option<Vec<_>> = reader.read_tangents().map(|tangents| tangents.collect());
+        let indices: Vec<_> = reader.read_indices()?.into_u32().collect();
+
+        // Mirroring transforms flip the orientation of the tangent frame
+        let handedness = transform.transform.determinant().signum();
         let mut vertices = Vec::with_capacity(positions.len());
         for i in 0..positions.len() {
+            let tangent = tangents.as_ref().map_or(Vec4::ZERO, |tangents| {
+                let [x, y, z, w] = tangents[i];
+                transform
+                    .transform
+                    .transform_vector3(Vec3::new(x, y, z))
+                    .extend(w * handedness)
+            });
             vertices.push(Vertex {
                 pos: transform
                     .transform
@@ -48,17 +62,27 @@ impl Primitives {
                 normal: transform
                     .inv_trans
                     .transform_vector3(Vec3::from_array(normals[i])),
+                tangent,
                 tex_coord: Vec2::from_array(tex_coords[i]),
             });
         }
 
+        if tangents.is_none() {
+            let mut geometry = TangentGeometry {
+                vertices: &mut vertices,
+                indices: &indices,
+            };
+            if !bevy_mikktspace::generate_tangents(&mut geometry) {
+                warn!("Failed to generate tangents for primitive {}", primitive.index());
+            }
+        }
+
         let primitive = self.offsets.len() as u32;
         let offset = self.vertices.len() as u32;
         self.vertices.append(&mut vertices);
         self.offsets.push(offset);
         self.material_indices.push(material_idx);
 
-        let indices: Vec<_> = reader.read_indices()?.into_u32().collect();
         let mut triangles = Vec::new();
         for i in 0..indices.len() / 3 {
             triangles.push(Triangle {
@@ -200,3 +224,42 @@ impl Primitives {
         Ok((bind_group_layout, bind_group))
     }
 }
+
+/// An indexed triangle list exposed to the MikkTSpace tangent generator
+struct TangentGeometry<'a> {
+    vertices: &'a mut [Vertex],
+    indices: &'a [u32],
+}
+
+impl TangentGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> &Vertex {
+        &self.vertices[self.indices[3 * face + vert] as usize]
+    }
+}
+
+impl Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).pos.to_array()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal.normalize_or_zero().to_array()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.vertex(face, vert).tex_coord.to_array()
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        let idx = self.indices[3 * face + vert] as usize;
+        self.vertices[idx].tangent = Vec4::from_array(tangent);
+    }
+}
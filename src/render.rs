@@ -1,13 +1,18 @@
+mod adaptive;
+mod auto_exposure;
+mod blue_noise;
 mod copy_to_screen;
+mod denoiser;
 mod post_processor;
 mod render_context;
 mod renderer;
 
 pub use copy_to_screen::CopyToScreen;
-pub use post_processor::PostProcessor;
+pub use post_processor::{PostProcessor, Rgba16Image};
 pub use render_context::RenderContext;
 pub use renderer::{BindGroupLayoutSet, BindGroupSet, Renderer};
 
+use crate::config::{Aov, Config};
 use divrem::DivCeil;
 
 /// Find minimum integer N where N % align_to == 0 and N >= num
@@ -17,3 +22,20 @@ where
 {
     num.div_ceil(align_to) * align_to
 }
+
+/// The AOVs rendered into the layers of the AOV target, in the order of their layers, including
+/// the guides of the denoiser
+fn aov_layers(config: &Config) -> Vec<Aov> {
+    let guides: &[Aov] = if config.denoise {
+        &[Aov::Albedo, Aov::Normal, Aov::Depth]
+    } else {
+        &[]
+    };
+    let mut aovs = Vec::new();
+    for &aov in config.aovs.iter().chain(guides) {
+        if !aovs.contains(&aov) {
+            aovs.push(aov);
+        }
+    }
+    aovs
+}
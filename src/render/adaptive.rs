@@ -0,0 +1,159 @@
+use super::align;
+use crate::config::{Config, Size};
+use anyhow::Result;
+use std::sync::mpsc;
+
+/// The samples of the image, counted on the GPU
+pub struct SampleCounts {
+    /// The number of pixels which are neither converged nor below the target noise
+    pub active_pixels: u64,
+    /// The total number of samples of the pixels
+    pub samples: u64,
+}
+
+/// Counts the pixels of the image which are still sampled and the samples taken, from the
+/// variance target of the renderer
+pub struct ActivePixelCounter {
+    aligned_width: u32,
+    aligned_height: u32,
+
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+    count_buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+}
+
+impl ActivePixelCounter {
+    pub fn new(device: &wgpu::Device, config: &Config, variance_view: &wgpu::TextureView) -> Self {
+        let Size { width, height } = config.size;
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sample counts"),
+            size: 12,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 12,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(variance_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: count_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader_module = device
+            .create_shader_module(wgpu::include_wgsl!("../../shaders-generated/adaptive.wgsl"));
+
+        // The same threshold as the one of the renderer, which marks pixels as converged
+        let target_noise = config
+            .noise_threshold
+            .or(config.target_noise)
+            .unwrap_or(0.0);
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: Some("count_active"),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &[
+                    ("WIDTH", width as f64),
+                    ("HEIGHT", height as f64),
+                    ("TARGET_NOISE", target_noise as f64),
+                ],
+                zero_initialize_workgroup_memory: true,
+            },
+            cache: None,
+        });
+
+        Self {
+            aligned_width: align(width, 16),
+            aligned_height: align(height, 16),
+            bind_group,
+            pipeline,
+            count_buffer,
+            staging_buffer,
+        }
+    }
+
+    /// Waits for the submitted samples and counts them
+    pub fn count(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<SampleCounts> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.clear_buffer(&self.count_buffer, 0, None);
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(self.aligned_width / 16, self.aligned_height / 16, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.count_buffer, 0, &self.staging_buffer, 0, 12);
+        queue.submit(Some(encoder.finish()));
+
+        let (tx, rx) = mpsc::channel();
+        let slice = self.staging_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| tx.send(result).unwrap());
+        device.poll(wgpu::MaintainBase::Wait)?;
+        rx.recv()??;
+
+        let words: Vec<u64> = slice
+            .get_mapped_range()
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes(word.try_into().unwrap()) as u64)
+            .collect();
+        self.staging_buffer.unmap();
+        Ok(SampleCounts {
+            active_pixels: words[0],
+            samples: words[1] | words[2] << 32,
+        })
+    }
+}
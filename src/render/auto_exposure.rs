@@ -0,0 +1,141 @@
+use super::align;
+use crate::config::{Config, Size};
+
+/// The number of bins of the log luminance histogram
+const NUM_BINS: u64 = 256;
+
+/// Derives the exposure of the image from a histogram of the log luminance of the render target,
+/// averaging the pixels between two percentiles
+pub struct AutoExposure {
+    aligned_width: u32,
+    aligned_height: u32,
+
+    bind_group: wgpu::BindGroup,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+}
+
+impl AutoExposure {
+    /// Creates the passes writing the exposure into `exposure_buffer`
+    pub fn new(
+        device: &wgpu::Device,
+        config: &Config,
+        src_view: &wgpu::TextureView,
+        exposure_buffer: &wgpu::Buffer,
+    ) -> Self {
+        let Size { width, height } = config.size;
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance histogram"),
+            size: NUM_BINS * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(src_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader_module = device.create_shader_module(wgpu::include_wgsl!(
+            "../../shaders-generated/histogram.wgsl"
+        ));
+
+        let constants = [
+            ("WIDTH", width as f64),
+            ("HEIGHT", height as f64),
+            ("LOW_PERCENTILE", config.auto_exposure_low as f64 / 100.0),
+            ("HIGH_PERCENTILE", config.auto_exposure_high as f64 / 100.0),
+        ];
+        let create_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    zero_initialize_workgroup_memory: true,
+                },
+                cache: None,
+            })
+        };
+
+        Self {
+            aligned_width: align(width, 16),
+            aligned_height: align(height, 16),
+            bind_group,
+            histogram_pipeline: create_pipeline("build_histogram"),
+            average_pipeline: create_pipeline("average_histogram"),
+        }
+    }
+
+    /// Records the passes building the histogram and averaging it into the exposure
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.set_pipeline(&self.histogram_pipeline);
+        compute_pass.dispatch_workgroups(self.aligned_width / 16, self.aligned_height / 16, 1);
+        compute_pass.set_pipeline(&self.average_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+}
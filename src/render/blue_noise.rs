@@ -0,0 +1,113 @@
+//! Generation of a tileable blue noise dither mask with Ulichney's void-and-cluster method.
+//!
+//! Points are ranked by repeatedly removing the tightest cluster from an initial pattern and then
+//! filling the largest voids, where clusters and voids are found from the energy of a toroidal
+//! Gaussian filter over the pattern.
+
+/// The standard deviation of the Gaussian filter, in pixels
+const SIGMA: f32 = 1.5;
+
+/// Returns `size * size` thresholds in [0, 1), each taken by exactly one pixel
+pub fn generate(size: usize) -> Vec<f32> {
+    let n = size * size;
+    let kernel: Vec<f32> = (0..n)
+        .map(|i| {
+            // Toroidal distance to the origin
+            let dx = (i % size).min(size - i % size) as f32;
+            let dy = (i / size).min(size - i / size) as f32;
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect();
+
+    let mut pattern = Pattern {
+        size,
+        kernel,
+        points: vec![false; n],
+        energy: vec![0.0; n],
+    };
+
+    // A random initial pattern covering a tenth of the pixels
+    let mut state = 0x9E37_79B9_u32;
+    let mut num_points = 0;
+    while num_points < n / 10 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let idx = state as usize % n;
+        if !pattern.points[idx] {
+            pattern.toggle(idx);
+            num_points += 1;
+        }
+    }
+
+    // Spread the initial points evenly by moving the tightest cluster into the largest void
+    loop {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; n];
+    let initial = pattern.points.clone();
+    let initial_energy = pattern.energy.clone();
+    for rank in (0..num_points).rev() {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        ranks[cluster] = rank;
+    }
+
+    pattern.points = initial;
+    pattern.energy = initial_energy;
+    for rank in num_points..n {
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        ranks[void] = rank;
+    }
+
+    ranks
+        .into_iter()
+        .map(|rank| (rank as f32 + 0.5) / n as f32)
+        .collect()
+}
+
+struct Pattern {
+    size: usize,
+    kernel: Vec<f32>,
+    points: Vec<bool>,
+    /// The filtered pattern at each pixel
+    energy: Vec<f32>,
+}
+
+impl Pattern {
+    fn toggle(&mut self, idx: usize) {
+        self.points[idx] = !self.points[idx];
+        let sign = if self.points[idx] { 1.0 } else { -1.0 };
+
+        let (x, y) = (idx % self.size, idx / self.size);
+        for (i, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (i % self.size + self.size - x) % self.size;
+            let dy = (i / self.size + self.size - y) % self.size;
+            *energy += sign * self.kernel[dy * self.size + dx];
+        }
+    }
+
+    /// The point with the most energy
+    fn tightest_cluster(&self) -> usize {
+        (0..self.points.len())
+            .filter(|&i| self.points[i])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+
+    /// The empty pixel with the least energy
+    fn largest_void(&self) -> usize {
+        (0..self.points.len())
+            .filter(|&i| !self.points[i])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+}
@@ -0,0 +1,193 @@
+use super::{align, aov_layers};
+use crate::config::{Aov, Config, Size};
+
+/// The number of iterations of the wavelet transform, whose last taps are 16 pixels apart
+const NUM_ITERATIONS: u32 = 5;
+
+/// Filters the accumulated radiance with an edge-avoiding à-trous wavelet transform, guided by the
+/// albedo, normal and depth AOVs of the first hits
+pub struct Denoiser {
+    aligned_width: u32,
+    aligned_height: u32,
+
+    /// The bind groups of the iterations, the first one reading the render target and the others
+    /// alternating between the textures
+    bind_groups: [wgpu::BindGroup; 3],
+    pipeline: wgpu::ComputePipeline,
+    textures: [wgpu::Texture; 2],
+}
+
+impl Denoiser {
+    /// Creates the passes filtering the radiance of `src_view`, guided by the AOVs of `aov_view`
+    /// and divided by the numbers of samples of `variance_view`
+    pub fn new(
+        device: &wgpu::Device,
+        config: &Config,
+        src_view: &wgpu::TextureView,
+        aov_view: &wgpu::TextureView,
+        variance_view: &wgpu::TextureView,
+    ) -> Self {
+        let Size { width, height } = config.size;
+        let aligned_width = align(width, 16);
+        let aligned_height = align(height, 16);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let textures = [0, 1].map(|_| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Denoiser target"),
+                size: wgpu::Extent3d {
+                    width: aligned_width,
+                    height: aligned_height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        });
+        let views = textures
+            .each_ref()
+            .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()));
+
+        let create_bind_group = |src_view: &wgpu::TextureView, dst_view: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(src_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(dst_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(aov_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(variance_view),
+                    },
+                ],
+            })
+        };
+        let bind_groups = [
+            create_bind_group(src_view, &views[0]),
+            create_bind_group(&views[0], &views[1]),
+            create_bind_group(&views[1], &views[0]),
+        ];
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStages::COMPUTE,
+                range: 0..4,
+            }],
+        });
+
+        let shader_module = device
+            .create_shader_module(wgpu::include_wgsl!("../../shaders-generated/denoise.wgsl"));
+
+        // The guides are always rendered when denoising
+        let aovs = aov_layers(config);
+        let layer = |aov| aovs.iter().position(|&rendered| rendered == aov).unwrap() as f64;
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: Some("main"),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &[
+                    ("NUM_ITERATIONS", NUM_ITERATIONS as f64),
+                    ("ALBEDO_LAYER", layer(Aov::Albedo)),
+                    ("NORMAL_LAYER", layer(Aov::Normal)),
+                    ("DEPTH_LAYER", layer(Aov::Depth)),
+                ],
+                zero_initialize_workgroup_memory: true,
+            },
+            cache: None,
+        });
+
+        Self {
+            aligned_width,
+            aligned_height,
+            bind_groups,
+            pipeline,
+            textures,
+        }
+    }
+
+    /// The texture holding the filtered radiance once the passes are done
+    pub fn output(&self) -> &wgpu::Texture {
+        &self.textures[(NUM_ITERATIONS as usize - 1) % 2]
+    }
+
+    /// Records the iterations of the filter
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        for iteration in 0..NUM_ITERATIONS {
+            let bind_group = match iteration {
+                0 => &self.bind_groups[0],
+                _ => &self.bind_groups[1 + (iteration as usize - 1) % 2],
+            };
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.set_push_constants(0, &iteration.to_ne_bytes());
+            compute_pass.dispatch_workgroups(self.aligned_width / 16, self.aligned_height / 16, 1);
+        }
+    }
+}
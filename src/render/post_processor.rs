@@ -1,9 +1,27 @@
-use super::{align, RenderContext};
-use crate::config::{Config, Size};
-use anyhow::Result;
-use image::RgbaImage;
+use super::{
+    align, aov_layers, auto_exposure::AutoExposure, blue_noise, denoiser::Denoiser, RenderContext,
+};
+use crate::config::{Aov, Config, Dither, Size, ToneMap};
+use anyhow::{anyhow, Result};
+use image::{ImageBuffer, Rgba, Rgba32FImage, RgbaImage};
 use std::{cell::RefCell, error::Error, rc::Rc, sync::mpsc};
 
+const TONE_MAP_LINEAR: u32 = 0;
+const TONE_MAP_REINHARD: u32 = 1;
+const TONE_MAP_ACES: u32 = 2;
+const TONE_MAP_AGX: u32 = 3;
+const TONE_MAP_NEUTRAL: u32 = 4;
+
+const DITHER_NONE: u32 = 0;
+const DITHER_TRIANGULAR: u32 = 1;
+const DITHER_BLUE_NOISE: u32 = 2;
+
+/// The width and height of the tiled blue noise mask
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// An image with 16 bits per channel
+pub type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
 pub struct PostProcessor {
     context: Rc<RefCell<RenderContext>>,
     width: u32,
@@ -14,11 +32,23 @@ pub struct PostProcessor {
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
     pipeline: wgpu::ComputePipeline,
+    src_texture: wgpu::Texture,
+    /// The radiance which is tone mapped, filtered if denoising
+    radiance_texture: wgpu::Texture,
     dst_texture: wgpu::Texture,
+    precise_texture: wgpu::Texture,
+    aov_texture: wgpu::Texture,
+    variance_texture: wgpu::Texture,
+    aovs: Vec<Aov>,
+    auto_exposure: Option<AutoExposure>,
+    denoiser: Option<Denoiser>,
 }
 
 impl PostProcessor {
-    pub fn new(context: Rc<RefCell<RenderContext>>, config: &Config) -> Self {
+    /// Creates the post processor, which scales the accumulated radiance by the exposure of the
+    /// camera, or the automatic exposure if enabled, and the exposure adjustment of the
+    /// configuration before tone mapping
+    pub fn new(context: Rc<RefCell<RenderContext>>, config: &Config, exposure: f32) -> Self {
         let bcontext = context.borrow();
         let device = bcontext.device();
 
@@ -49,6 +79,46 @@ impl PostProcessor {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -62,13 +132,36 @@ impl PostProcessor {
             "../../shaders-generated/post_process.wgsl"
         ));
 
+        let manual_exposure = if config.auto_exposure { 1.0 } else { exposure };
+        let tone_map = match config.tone_map {
+            ToneMap::Linear => TONE_MAP_LINEAR,
+            ToneMap::Reinhard => TONE_MAP_REINHARD,
+            ToneMap::Aces => TONE_MAP_ACES,
+            ToneMap::Agx => TONE_MAP_AGX,
+            ToneMap::Neutral => TONE_MAP_NEUTRAL,
+        };
+        let dither = match config.dither {
+            Dither::None => DITHER_NONE,
+            Dither::Triangular => DITHER_TRIANGULAR,
+            Dither::BlueNoise => DITHER_BLUE_NOISE,
+        };
+
         let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: None,
             layout: Some(&pipeline_layout),
             module: &shader_module,
             entry_point: Some("main"),
             compilation_options: wgpu::PipelineCompilationOptions {
-                constants: &[("NUM_SAMPLES", config.samples as f64)],
+                constants: &[
+                    (
+                        "EXPOSURE",
+                        (manual_exposure * config.exposure.exp2()) as f64,
+                    ),
+                    ("TONE_MAP", tone_map as f64),
+                    ("WHITE_POINT", config.white_point.unwrap_or(0.0) as f64),
+                    ("DITHER", dither as f64),
+                    ("BLUE_NOISE_SIZE", BLUE_NOISE_SIZE as f64),
+                ],
                 zero_initialize_workgroup_memory: true,
                 // vertex_pulling_transform: false,
             },
@@ -92,12 +185,74 @@ impl PostProcessor {
             view_formats: &[],
         });
 
-        let src_view = bcontext
-            .rt_render_target
-            .as_ref()
-            .unwrap()
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let precise_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Post process precise target"),
+            size: wgpu::Extent3d {
+                width: aligned_width,
+                height: aligned_height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let src_texture = bcontext.rt_render_target.clone().unwrap();
+        let aov_texture = bcontext.rt_aov_target.clone().unwrap();
+        let variance_texture = bcontext.rt_variance_target.clone().unwrap();
+        let variance_view = variance_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let aov_view = aov_texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let denoiser = config.denoise.then(|| {
+            let src_view = src_texture.create_view(&wgpu::TextureViewDescriptor::default());
+            Denoiser::new(device, config, &src_view, &aov_view, &variance_view)
+        });
+        let radiance_texture = match &denoiser {
+            Some(denoiser) => denoiser.output().clone(),
+            None => src_texture.clone(),
+        };
+        let src_view = radiance_texture.create_view(&wgpu::TextureViewDescriptor::default());
         let dst_view = dst_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let precise_view = precise_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // The mask is only generated when used, a single threshold stands in for it otherwise
+        let blue_noise = match config.dither {
+            Dither::BlueNoise => blue_noise::generate(BLUE_NOISE_SIZE),
+            _ => vec![0.5],
+        };
+        let blue_noise_bytes: Vec<u8> = blue_noise
+            .iter()
+            .flat_map(|threshold| threshold.to_ne_bytes())
+            .collect();
+        let blue_noise_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Blue noise"),
+            size: blue_noise_bytes.len() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        bcontext
+            .queue()
+            .write_buffer(&blue_noise_buffer, 0, &blue_noise_bytes);
+
+        // Scales the image in addition to EXPOSURE, which is left at one unless written by
+        // automatic exposure
+        let exposure_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Automatic exposure"),
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        bcontext
+            .queue()
+            .write_buffer(&exposure_buffer, 0, &1.0_f32.to_ne_bytes());
+        let auto_exposure = config
+            .auto_exposure
+            .then(|| AutoExposure::new(device, config, &src_view, &exposure_buffer));
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
@@ -111,6 +266,22 @@ impl PostProcessor {
                     binding: 1,
                     resource: wgpu::BindingResource::TextureView(&dst_view),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&precise_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: blue_noise_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&variance_view),
+                },
             ],
         });
 
@@ -127,7 +298,15 @@ impl PostProcessor {
             bind_group_layout,
             bind_group,
             pipeline,
+            src_texture,
+            radiance_texture,
             dst_texture,
+            precise_texture,
+            aov_texture,
+            variance_texture,
+            aovs: aov_layers(config),
+            auto_exposure,
+            denoiser,
         }
     }
 
@@ -138,6 +317,13 @@ impl PostProcessor {
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
 
+        if let Some(denoiser) = &self.denoiser {
+            denoiser.encode(&mut encoder);
+        }
+        if let Some(auto_exposure) = &self.auto_exposure {
+            auto_exposure.encode(&mut encoder);
+        }
+
         {
             let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: None,
@@ -151,15 +337,102 @@ impl PostProcessor {
         queue.submit(Some(encoder.finish()));
     }
 
+    /// The width and height of the image
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
     pub async fn retrieve_result(&self) -> Result<Option<RgbaImage>> {
+        let bytes = self.read_back(&self.dst_texture, 0, 4).await?;
+        Ok(RgbaImage::from_raw(self.width, self.height, bytes))
+    }
+
+    /// Reads back the post processed image before its quantization to 8 bits
+    pub async fn retrieve_result_16(&self) -> Result<Rgba16Image> {
+        let values = self.read_back_f32(&self.precise_texture, 0).await?;
+        let values = values
+            .into_iter()
+            .map(|value| (value.clamp(0.0, 1.0) * 65535.0).round() as u16)
+            .collect();
+        Ok(Rgba16Image::from_raw(self.width, self.height, values).unwrap())
+    }
+
+    /// Reads back the linear radiance accumulated by the renderer, divided by the number of
+    /// samples and filtered if denoising. Transparent background samples contribute no color, so
+    /// the color channels are premultiplied by alpha.
+    pub async fn retrieve_radiance(&self) -> Result<Rgba32FImage> {
+        self.read_back_radiance(&self.radiance_texture).await
+    }
+
+    /// Reads back the linear radiance like [`Self::retrieve_radiance`], but never filtered
+    pub async fn retrieve_unfiltered_radiance(&self) -> Result<Rgba32FImage> {
+        self.read_back_radiance(&self.src_texture).await
+    }
+
+    async fn read_back_radiance(&self, texture: &wgpu::Texture) -> Result<Rgba32FImage> {
+        let values = self.read_back_f32(texture, 0).await?;
+        self.average(values).await
+    }
+
+    /// Reads back the number of samples of each pixel
+    pub async fn retrieve_sample_counts(&self) -> Result<Vec<f32>> {
+        let values = self.read_back_f32(&self.variance_texture, 0).await?;
+        Ok(values.chunks_exact(4).map(|variance| variance[2]).collect())
+    }
+
+    /// Divides sums over the samples of each pixel by their number
+    async fn average(&self, sums: Vec<f32>) -> Result<Rgba32FImage> {
+        let sample_counts = self.retrieve_sample_counts().await?;
+        let values = sums
+            .chunks_exact(4)
+            .zip(sample_counts)
+            .flat_map(|(sum, samples)| sum.iter().map(move |value| value / samples.max(1.0)))
+            .collect();
+        Ok(Rgba32FImage::from_raw(self.width, self.height, values).unwrap())
+    }
+
+    /// Reads back an AOV of the first hits, averaged over the samples except for the indices,
+    /// which are taken from the first sample
+    pub async fn retrieve_aov(&self, aov: Aov) -> Result<Rgba32FImage> {
+        let layer = self
+            .aovs
+            .iter()
+            .position(|&rendered| rendered == aov)
+            .ok_or_else(|| anyhow!("The AOV was not rendered"))?;
+        let values = self.read_back_f32(&self.aov_texture, layer as u32).await?;
+        match aov {
+            Aov::Material | Aov::Primitive => {
+                Ok(Rgba32FImage::from_raw(self.width, self.height, values).unwrap())
+            }
+            _ => self.average(values).await,
+        }
+    }
+
+    async fn read_back_f32(&self, texture: &wgpu::Texture, layer: u32) -> Result<Vec<f32>> {
+        let bytes = self.read_back(texture, layer, 16).await?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|value| f32::from_ne_bytes(value.try_into().unwrap()))
+            .collect())
+    }
+
+    /// Copies the visible part of a layer of a texture to the CPU, returning its rows without
+    /// padding
+    async fn read_back(
+        &self,
+        texture: &wgpu::Texture,
+        layer: u32,
+        texel_size: u32,
+    ) -> Result<Vec<u8>> {
         let context = self.context.borrow();
         let device = context.device();
         let queue = context.queue();
 
-        let padded_width = align(self.width, 64);
+        // Rows are padded to 256 bytes
+        let padded_row = align(self.width * texel_size, 256);
         let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
-            size: (padded_width * self.height * 4) as u64,
+            size: (padded_row * self.height) as u64,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
             mapped_at_creation: false,
         });
@@ -168,16 +441,20 @@ impl PostProcessor {
 
         encoder.copy_texture_to_buffer(
             wgpu::TexelCopyTextureInfo {
-                texture: &self.dst_texture,
+                texture,
                 mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
                 aspect: wgpu::TextureAspect::All,
             },
             wgpu::TexelCopyBufferInfo {
                 buffer: &staging_buffer,
                 layout: wgpu::TexelCopyBufferLayout {
                     offset: 0,
-                    bytes_per_row: Some(padded_width * 4),
+                    bytes_per_row: Some(padded_row),
                     rows_per_image: None,
                 },
             },
@@ -196,22 +473,16 @@ impl PostProcessor {
         device.poll(wgpu::MaintainBase::Wait)?;
         rx.recv()??;
 
-        let mut buffer = Vec::new();
+        let row_size = (self.width * texel_size) as usize;
+        let mut buffer = Vec::with_capacity(row_size * self.height as usize);
         {
             let view = slice.get_mapped_range();
-            buffer.extend_from_slice(&view[..]);
-        }
-        staging_buffer.unmap();
-
-        let mut image_buffer = Vec::with_capacity((self.width * self.height * 4) as usize);
-        for y in 0..self.height {
-            for x in 0..self.width {
-                for i in 0..4 {
-                    image_buffer.push(buffer[((y * padded_width + x) * 4 + i) as usize]);
-                }
+            for row in view.chunks_exact(padded_row as usize) {
+                buffer.extend_from_slice(&row[..row_size]);
             }
         }
+        staging_buffer.unmap();
 
-        Ok(RgbaImage::from_raw(self.width, self.height, image_buffer))
+        Ok(buffer)
     }
 }
@@ -5,6 +5,8 @@ pub struct RenderContext {
     queue: wgpu::Queue,
 
     pub rt_render_target: Option<wgpu::Texture>,
+    pub rt_aov_target: Option<wgpu::Texture>,
+    pub rt_variance_target: Option<wgpu::Texture>,
     pub postprocess_target: Option<wgpu::Texture>,
 }
 
@@ -28,6 +30,7 @@ impl RenderContext {
                     max_push_constant_size: 4,
                     max_texture_dimension_2d: 4096,
                     max_binding_array_elements_per_shader_stage: 100,
+                    max_storage_buffers_per_shader_stage: 8,
                     ..wgpu::Limits::downlevel_defaults()
                 },
                 memory_hints: wgpu::MemoryHints::Performance,
@@ -39,6 +42,8 @@ impl RenderContext {
             device,
             queue,
             rt_render_target: None,
+            rt_aov_target: None,
+            rt_variance_target: None,
             postprocess_target: None,
         })
     }
@@ -48,6 +53,8 @@ impl RenderContext {
             device,
             queue,
             rt_render_target: None,
+            rt_aov_target: None,
+            rt_variance_target: None,
             postprocess_target: None,
         }
     }
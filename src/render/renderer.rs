@@ -1,19 +1,49 @@
-use super::{align, RenderContext};
-use crate::config::{Config, Size};
+use super::{adaptive::ActivePixelCounter, align, aov_layers, RenderContext};
+use crate::config::{Aov, Config, Size};
 use anyhow::Result;
 use indicatif::ProgressBar;
-use log::{debug, trace};
-use std::{cell::RefCell, default::Default, error::Error, rc::Rc, sync::Arc};
+use log::{info, trace};
+use std::{
+    cell::{Cell, RefCell},
+    default::Default,
+    error::Error,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use wgpu::{Texture, TextureUsages};
 
+/// The number of samples rendered between counts of the pixels which are not converged
+const BATCH_SAMPLES: u64 = 16;
+
+/// The maximum number of samples of a pixel with adaptive sampling or a target noise, relative to
+/// the number of samples
+const MAX_SAMPLES_FACTOR: u32 = 8;
+
 pub struct Renderer {
     context: Rc<RefCell<RenderContext>>,
     width: u32,
     height: u32,
+    /// The number of pixels of the image, without the alignment
+    pixels: u64,
     samples: u32,
+    /// Samples are taken until the time limit or the target noise is reached instead
+    time_limit: Option<Duration>,
+    target_noise: bool,
+    /// When the time limit runs out, counted from the first render as the viewer renders again
+    /// each frame
+    deadline: Cell<Option<Instant>>,
+    /// The index of the next sample, which continues across renders
+    next_sample: Cell<u32>,
     target_bind_group_layout: wgpu::BindGroupLayout,
     pipeline: wgpu::ComputePipeline,
     render_target: wgpu::Texture,
+    aov_target: wgpu::Texture,
+    variance_target: wgpu::Texture,
+    /// Counts the pixels still sampled and the samples taken
+    active_pixel_counter: ActivePixelCounter,
+    /// Whether the pixels still sampled are counted after each batch of samples
+    count_batches: bool,
 }
 
 #[derive(Clone)]
@@ -48,16 +78,38 @@ impl Renderer {
         let target_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: None,
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::ReadWrite,
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::ReadWrite,
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::ReadWrite,
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::ReadWrite,
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
             });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -78,13 +130,39 @@ impl Renderer {
         let shader_module =
             device.create_shader_module(wgpu::include_wgsl!("../../shaders-generated/render.wgsl"));
 
+        let aovs = aov_layers(config);
+        let mut constants = vec![
+            ("MAX_DEPTH", config.depth as f64),
+            ("USE_OCCLUSION", config.occlusion as u32 as f64),
+        ];
+        for (layer, &aov) in aovs.iter().enumerate() {
+            let name = match aov {
+                Aov::Albedo => "AOV_ALBEDO",
+                Aov::Normal => "AOV_NORMAL",
+                Aov::Position => "AOV_POSITION",
+                Aov::Depth => "AOV_DEPTH",
+                Aov::Uv => "AOV_UV",
+                Aov::Material => "AOV_MATERIAL",
+                Aov::Primitive => "AOV_PRIMITIVE",
+            };
+            constants.push((name, layer as f64));
+        }
+        // A target noise stops pixels at the same threshold as the active pixel counter
+        if let Some(noise_threshold) = config.noise_threshold.or(config.target_noise) {
+            constants.push(("NOISE_THRESHOLD", noise_threshold as f64));
+        }
+        if config.noise_threshold.is_some() || config.target_noise.is_some() {
+            let max_samples = config.samples.saturating_mul(MAX_SAMPLES_FACTOR);
+            constants.push(("MAX_SAMPLES", max_samples as f64));
+        }
+
         let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: None,
             layout: Some(&pipeline_layout),
             module: &shader_module,
             entry_point: Some("main"),
             compilation_options: wgpu::PipelineCompilationOptions {
-                constants: &[("MAX_DEPTH", config.depth as f64)],
+                constants: &constants,
                 zero_initialize_workgroup_memory: true,
             },
             cache: None,
@@ -101,25 +179,74 @@ impl Renderer {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba32Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        // A layer is allocated even without AOVs, as the binding cannot be empty
+        let aov_target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("RayTracing renderer AOV target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: aovs.len().max(1) as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
 
+        let variance_target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("RayTracing renderer variance target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let variance_view = variance_target.create_view(&wgpu::TextureViewDescriptor::default());
+        let active_pixel_counter = ActivePixelCounter::new(device, config, &variance_view);
+
         std::mem::drop(bcontext);
         let mut mcontext = context.borrow_mut();
         mcontext.rt_render_target.replace(render_target.clone());
+        mcontext.rt_aov_target.replace(aov_target.clone());
+        mcontext.rt_variance_target.replace(variance_target.clone());
 
         Self {
             context: context.clone(),
             width,
             height,
+            pixels: config.size.width as u64 * config.size.height as u64,
             samples: config.samples,
+            time_limit: config.time_limit.map(Duration::from_secs_f32),
+            target_noise: config.target_noise.is_some(),
+            deadline: Cell::new(None),
+            next_sample: Cell::new(0),
             target_bind_group_layout,
             pipeline,
             render_target,
+            aov_target,
+            variance_target,
+            active_pixel_counter,
+            count_batches: config.noise_threshold.is_some()
+                || config.target_noise.is_some()
+                || config.time_limit.is_some(),
         }
     }
 
+    /// Renders `samples` samples per pixel on average, or until the time limit or the target noise
+    /// is reached. When sampling adaptively, converged pixels stop being sampled and their samples
+    /// are spent on the remaining ones instead
     pub fn render(&self, bind_group_set: BindGroupSet) -> Result<()> {
         let context = self.context.borrow();
         let device = context.device();
@@ -128,47 +255,120 @@ impl Renderer {
         let view = self
             .render_target
             .create_view(&wgpu::TextureViewDescriptor::default());
+        let variance_view = self
+            .variance_target
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let aov_view = self.aov_target.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
 
         let output_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &self.target_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&view),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&aov_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&variance_view),
+                },
+            ],
         });
 
         // let progress_bar = Arc::new(ProgressBar::new(self.samples as u64));
 
-        for sample in 0..self.samples {
-            let mut encoder =
-                device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-
-            {
-                let sample: [u8; 4] = sample.to_ne_bytes();
-
-                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: None,
-                    timestamp_writes: None,
-                });
-                compute_pass.set_pipeline(&self.pipeline);
-                compute_pass.set_bind_group(0, &output_bind_group, &[]);
-                compute_pass.set_bind_group(1, &bind_group_set.scene, &[]);
-                compute_pass.set_bind_group(2, &bind_group_set.primitive, &[]);
-                compute_pass.set_bind_group(3, &bind_group_set.material, &[]);
-                compute_pass.set_bind_group(4, &bind_group_set.texture, &[]);
-                compute_pass.set_push_constants(0, &sample);
-                compute_pass.dispatch_workgroups(self.width / 16, self.height / 16, 1);
+        let start = Instant::now();
+        let deadline = self.time_limit.map(|time_limit| {
+            let deadline = self.deadline.get().unwrap_or(start + time_limit);
+            self.deadline.set(Some(deadline));
+            deadline
+        });
+        let budget = if self.time_limit.is_some() || self.target_noise {
+            u64::MAX
+        } else {
+            self.samples as u64 * self.pixels
+        };
+        let mut spent = 0;
+        let mut active_pixels = self.pixels;
+        let mut passes = 0;
+        while active_pixels > 0 && spent < budget {
+            // Spread the remaining samples over the active pixels
+            let mut batch = (budget - spent).div_ceil(active_pixels).min(BATCH_SAMPLES);
+            if let Some(deadline) = deadline {
+                // Estimate the passes left before the deadline from the duration of the previous
+                // ones
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if passes > 0 {
+                    let pass_time = start.elapsed().as_secs_f64() / passes as f64;
+                    batch = batch.min((remaining.as_secs_f64() / pass_time) as u64);
+                } else if remaining.is_zero() {
+                    batch = 0;
+                }
+            }
+            if batch == 0 {
+                break;
             }
 
-            // let progress_bar = progress_bar.clone();
-            queue.submit(Some(encoder.finish()));
-            // queue.on_submitted_work_done(move || progress_bar.inc(1));
+            for _ in 0..batch {
+                let sample = self.next_sample.get();
+                self.next_sample.set(sample + 1);
+
+                let mut encoder =
+                    device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+                {
+                    let sample: [u8; 4] = sample.to_ne_bytes();
+
+                    let mut compute_pass =
+                        encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                            label: None,
+                            timestamp_writes: None,
+                        });
+                    compute_pass.set_pipeline(&self.pipeline);
+                    compute_pass.set_bind_group(0, &output_bind_group, &[]);
+                    compute_pass.set_bind_group(1, &bind_group_set.scene, &[]);
+                    compute_pass.set_bind_group(2, &bind_group_set.primitive, &[]);
+                    compute_pass.set_bind_group(3, &bind_group_set.material, &[]);
+                    compute_pass.set_bind_group(4, &bind_group_set.texture, &[]);
+                    compute_pass.set_push_constants(0, &sample);
+                    compute_pass.dispatch_workgroups(self.width / 16, self.height / 16, 1);
+                }
+
+                // let progress_bar = progress_bar.clone();
+                queue.submit(Some(encoder.finish()));
+                // queue.on_submitted_work_done(move || progress_bar.inc(1));
+            }
+            passes += batch;
+
+            spent += batch * active_pixels;
+            if self.count_batches {
+                active_pixels = self
+                    .active_pixel_counter
+                    .count(device, queue)?
+                    .active_pixels;
+                trace!("{active_pixels} pixels are still sampled after {passes} passes");
+            }
         }
 
         device.poll(wgpu::MaintainBase::Wait)?;
         // progress_bar.finish_and_clear();
 
+        // The post processor divides each pixel by its number of samples, which accumulate across
+        // renders
+        let counts = self.active_pixel_counter.count(device, queue)?;
+        info!(
+            "Rendered {passes} passes in {:.2?}, the image has {:.1} samples per pixel on average",
+            start.elapsed(),
+            counts.samples as f64 / self.pixels as f64
+        );
+
         Ok(())
     }
 
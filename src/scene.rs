@@ -1,29 +1,37 @@
 use self::bvh::Bvh;
 use crate::{
-    config::Config,
+    config::{Background, Config},
     core::{Aabb3, Triangle},
     materials::Materials,
     primitives::Primitives,
     render::{BindGroupLayoutSet, BindGroupSet, RenderContext},
     textures::Textures,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use encase::{ShaderType, StorageBuffer, UniformBuffer};
-use glam::{Mat4, Quat, Vec3};
+use glam::{Mat3, Mat4, Quat, Vec3};
 use gltf::{buffer, camera::Projection, image, scene, Node};
 use indicatif::ProgressBar;
+use log::{info, warn};
 use std::time::Duration;
 use std::{cell::RefCell, error::Error, rc::Rc};
 
 mod bvh;
 mod camera;
+mod import;
+mod lights;
+mod sky;
 
+use camera::Lens;
 pub use camera::{Camera, CameraBuilder};
+use lights::Lights;
+use sky::Sky;
 
 pub struct Scene {
     pub primitives: Primitives,
     pub materials: Materials,
     pub textures: Textures,
+    pub lights: Lights,
     pub uniform: Uniform,
     triangle_infos: Vec<TriangleInfo>,
 
@@ -33,10 +41,82 @@ pub struct Scene {
     pub bind_group: BindGroupSet,
 }
 
+/// Camera rays that escape the scene see the environment map
+const BACKGROUND_ENVIRONMENT: u32 = 0;
+/// Camera rays that escape the scene see `background_color`
+const BACKGROUND_COLOR: u32 = 1;
+/// Camera rays that escape the scene see the `background` panorama
+const BACKGROUND_IMAGE: u32 = 2;
+/// Camera rays that escape the scene are transparent in the output
+const BACKGROUND_TRANSPARENT: u32 = 3;
+
 #[derive(Default, ShaderType)]
 pub struct Uniform {
     pub camera: Camera,
     hdri: u32,
+    /// Rotates world space directions into the space of the environment map
+    hdri_rotation: Mat3,
+    hdri_intensity: f32,
+    background_mode: u32,
+    background: u32,
+    background_color: Vec3,
+    sky: Sky,
+    num_lights: u32,
+}
+
+/// Collects the meshes, lights and cameras of a node hierarchy
+#[derive(Default)]
+struct NodeLoader<'a> {
+    primitives: Primitives,
+    materials: Materials,
+    lights: Lights,
+    triangle_infos: Vec<TriangleInfo>,
+    cameras: Vec<(Node<'a>, Mat4)>,
+}
+
+impl<'a> NodeLoader<'a> {
+    fn load_nodes(&mut self, node: Node<'a>, buffers: &[buffer::Data], parent_transform: &Mat4) {
+        let transform_matrix = *parent_transform * transform_to_matrix(&node.transform());
+        let transform = Transform::new(transform_matrix);
+
+        if let Some(mesh) = node.mesh().filter(|_| lights::is_portal(&node)) {
+            if let Err(err) = self.lights.add_portal(&mesh, buffers, &transform_matrix) {
+                warn!("{err}");
+            }
+        } else if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                let material_idx = self.materials.add(&primitive.material()).unwrap();
+                self.triangle_infos.append(
+                    &mut self
+                        .primitives
+                        .add(buffers, &primitive, &transform, material_idx)
+                        .unwrap()
+                        .into_iter()
+                        .map(|triangle| {
+                            let aabb = triangle.aabb(&self.primitives);
+                            TriangleInfo {
+                                triangle,
+                                aabb,
+                                centroid: aabb.centroid(),
+                            }
+                        })
+                        .collect(),
+                );
+            }
+        }
+
+        if let Some(light) = node.light() {
+            self.lights.add(&light, &transform_matrix);
+        }
+
+        if node.camera().is_some() {
+            self.cameras.push((node.clone(), transform_matrix));
+        }
+
+        for child in node.children() {
+            self.load_nodes(child, buffers, &transform_matrix);
+        }
+    }
 }
 
 pub struct Transform {
@@ -67,31 +147,63 @@ impl Scene {
         buffers: &[buffer::Data],
         images: &[image::Data],
     ) -> Result<Self> {
-        let mut primitives = Primitives::new();
-        let mut materials = Materials::new();
-        let mut textures = Textures::new(context.clone());
+        let mut textures = Textures::new(context.clone(), config.max_texture_size);
         let mut uniform = Uniform::default();
-        let mut triangle_infos = Vec::new();
 
-        Self::load_images(&mut textures, images);
+        let mut loader = NodeLoader::default();
         for node in scene.nodes() {
-            Self::load_nodes(
-                &mut materials,
-                &mut triangle_infos,
-                &mut primitives,
-                &mut uniform,
-                node,
-                config,
-                buffers,
-                &Mat4::IDENTITY,
-            );
+            loader.load_nodes(node, buffers, &Mat4::IDENTITY);
+        }
+        let NodeLoader {
+            primitives,
+            materials,
+            mut lights,
+            mut triangle_infos,
+            cameras,
+        } = loader;
+
+        // The materials tell which images hold linear data
+        Self::load_images(&mut textures, &materials, images);
+        uniform.camera = Self::select_camera(&cameras, &triangle_infos, config)?;
+
+        // The sky replaces the environment map, which is then not loaded
+        uniform.hdri = if config.sky {
+            textures.add_placeholder_hdr()?
+        } else {
+            textures.load_texture_hdr(&config.hdri)?
+        };
+        uniform.hdri_rotation = (Mat3::from_rotation_y(config.hdri_yaw.to_radians())
+            * Mat3::from_rotation_x(config.hdri_pitch.to_radians()))
+        .transpose();
+        uniform.hdri_intensity = config.hdri_exposure.exp2();
+        if config.sky {
+            uniform.sky = Sky::from_config(config)?;
+        }
+        match &config.background {
+            Background::Environment => uniform.background_mode = BACKGROUND_ENVIRONMENT,
+            Background::Transparent => uniform.background_mode = BACKGROUND_TRANSPARENT,
+            Background::Color(color) => {
+                uniform.background_mode = BACKGROUND_COLOR;
+                uniform.background_color = *color;
+            }
+            Background::Image(path) => {
+                uniform.background_mode = BACKGROUND_IMAGE;
+                uniform.background = textures.load_texture_hdr(path)?;
+            }
         }
 
-        let hdri = textures.load_texture_hdr(&config.hdri)?;
-        uniform.hdri = hdri;
+        if let Some(path) = &config.lights {
+            lights.load_area_lights(path)?;
+        }
+        uniform.num_lights = lights.len() as u32;
 
-        let (bind_group_layout, bind_group, uniform_buffer) =
-            Self::build(&uniform, &primitives, &mut triangle_infos, context.clone())?;
+        let (bind_group_layout, bind_group, uniform_buffer) = Self::build(
+            &uniform,
+            &primitives,
+            &lights,
+            &mut triangle_infos,
+            context.clone(),
+        )?;
 
         let (scene_bind_group_layout, scene_bind_group) = (bind_group_layout, bind_group);
         let (primitive_bind_group_layout, primitive_bind_group) =
@@ -103,6 +215,7 @@ impl Scene {
             primitives,
             materials,
             textures,
+            lights,
             uniform,
             triangle_infos,
 
@@ -123,76 +236,87 @@ impl Scene {
         })
     }
 
-    fn load_images(textures: &mut Textures, images: &[image::Data]) {
-        for image in images {
-            textures.add_texture(image);
+    fn load_images(textures: &mut Textures, materials: &Materials, images: &[image::Data]) {
+        for (index, image) in images.iter().enumerate() {
+            textures.add_texture(image, materials.is_linear(index as u32));
         }
     }
 
-    fn load_nodes(
-        materials: &mut Materials,
-        triangle_infos: &mut Vec<TriangleInfo>,
-        primitives: &mut Primitives,
-        uniform: &mut Uniform,
-        node: Node,
+    /// Builds the camera chosen by name or index, the first camera of the scene otherwise, or a
+    /// camera framing the whole scene if there is none
+    fn select_camera(
+        cameras: &[(Node, Mat4)],
+        triangle_infos: &[TriangleInfo],
         config: &Config,
-        buffers: &[buffer::Data],
-        parent_transform: &Mat4,
-    ) {
-        let transform_matrix = *parent_transform * transform_to_matrix(&node.transform());
-        let transform = Transform::new(transform_matrix);
-
-        if let Some(mesh) = node.mesh() {
-            for primitive in mesh.primitives() {
-                let material_idx = materials.add(&primitive.material()).unwrap();
-                triangle_infos.append(
-                    &mut primitives
-                        .add(buffers, &primitive, &transform, material_idx)
-                        .unwrap()
-                        .into_iter()
-                        .map(|triangle| {
-                            let aabb = triangle.aabb(primitives);
-                            TriangleInfo {
-                                triangle,
-                                aabb,
-                                centroid: aabb.centroid(),
+    ) -> Result<Camera> {
+        let selected = match &config.camera {
+            Some(selection) => Some(
+                cameras
+                    .iter()
+                    .find(|(node, _)| {
+                        let camera = node.camera().unwrap();
+                        selection.parse() == Ok(camera.index())
+                            || camera.name() == Some(selection)
+                            || node.name() == Some(selection)
+                    })
+                    .ok_or_else(|| {
+                        let names: Vec<_> = cameras
+                            .iter()
+                            .map(|(node, _)| {
+                                let camera = node.camera().unwrap();
+                                format!("{} ({})", camera.index(), camera.name().unwrap_or(""))
+                            })
+                            .collect();
+                        anyhow!(
+                            "Camera {selection:?} not found, the scene has {}",
+                            if names.is_empty() {
+                                String::from("no cameras")
+                            } else {
+                                names.join(", ")
                             }
-                        })
-                        .collect(),
-                );
-            }
-        }
-
-        if let Some(camera) = node.camera() {
+                        )
+                    })?,
+            ),
+            None => cameras.first(),
+        };
+
+        let mut camera_builder = CameraBuilder::new();
+        if let Some((node, transform)) = selected {
+            let camera = node.camera().unwrap();
+            camera_builder.transform(*transform);
             match camera.projection() {
                 Projection::Perspective(perspective) => {
-                    let mut camera_builder = CameraBuilder::new();
-                    camera_builder
-                        .transform(transform_matrix)
-                        .yfov(perspective.yfov());
-                    uniform.camera = camera_builder.build(config.size.width, config.size.height);
+                    camera_builder.yfov(perspective.yfov());
+                }
+                Projection::Orthographic(orthographic) => {
+                    camera_builder.orthographic(orthographic.xmag(), orthographic.ymag());
                 }
-                _ => todo!(),
             }
+            camera_builder
+                .view(config)?
+                .lens(&Lens::from_extras(camera.extras()), config)
+                .exposure(config);
+        } else {
+            let bounds = triangle_infos
+                .iter()
+                .fold(Aabb3::new(), |bounds, info| bounds.union(&info.aabb));
+            if !triangle_infos.is_empty() {
+                info!("The scene has no camera, framing its bounds");
+                camera_builder.frame(&bounds, config);
+            }
+            // The view settings override the framed position and target
+            camera_builder
+                .view(config)?
+                .lens(&Lens::default(), config)
+                .exposure(config);
         }
-
-        for child in node.children() {
-            Self::load_nodes(
-                materials,
-                triangle_infos,
-                primitives,
-                uniform,
-                child,
-                config,
-                buffers,
-                &transform_matrix,
-            );
-        }
+        Ok(camera_builder.build(config.size.width, config.size.height))
     }
 
     pub fn build(
         uniform: &Uniform,
         primitives: &Primitives,
+        lights: &Lights,
         triangle_infos: &mut Vec<TriangleInfo>,
         context: Rc<RefCell<RenderContext>>,
     ) -> encase::internal::Result<(wgpu::BindGroupLayout, wgpu::BindGroup, wgpu::Buffer)> {
@@ -242,6 +366,18 @@ impl Scene {
         });
         queue.write_buffer(&bvh_buffer, 0, &wgsl_bytes);
 
+        let mut wgsl_bytes = StorageBuffer::new(Vec::new());
+        wgsl_bytes.write(lights.buffer_data())?;
+        let wgsl_bytes = wgsl_bytes.into_inner();
+
+        let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: wgsl_bytes.len() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&light_buffer, 0, &wgsl_bytes);
+
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
             entries: &[
@@ -275,6 +411,16 @@ impl Scene {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -294,6 +440,10 @@ impl Scene {
                     binding: 2,
                     resource: bvh_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: light_buffer.as_entire_binding(),
+                },
             ],
         });
 
@@ -334,7 +484,7 @@ pub fn build_scene(context: Rc<RefCell<RenderContext>>, config: &Config) -> Resu
     bar.enable_steady_tick(Duration::from_millis(100));
     bar.set_message("Parsing and loading the scene...");
 
-    let (document, buffers, images) = gltf::import(&config.scene)?;
+    let (document, buffers, images) = import::import(&config.scene)?;
 
     let scene = Scene::new(
         context.clone(),
@@ -1,10 +1,72 @@
+use crate::{
+    config::{Config, Projection, Size},
+    core::Aabb3,
+};
+use anyhow::{bail, Result};
 use encase::ShaderType;
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
+use gltf::json::Extras;
+use log::warn;
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+const CAMERA_PERSPECTIVE: u32 = 0;
+const CAMERA_ORTHOGRAPHIC: u32 = 1;
+const CAMERA_EQUIRECTANGULAR: u32 = 2;
+const CAMERA_STEREO_PANORAMA: u32 = 3;
+const CAMERA_EQUIDISTANT: u32 = 4;
+const CAMERA_EQUISOLID: u32 = 5;
+const CAMERA_STEREOGRAPHIC: u32 = 6;
+const CAMERA_BROWN_CONRADY: u32 = 7;
+
+/// Height of the 35 mm full frame sensor in meters, which relates the f-number to the focal length
+const SENSOR_HEIGHT: f32 = 0.024;
+/// The sensitivity and shutter time assumed when only one of them is given
+const DEFAULT_ISO: f32 = 100.0;
+const DEFAULT_SHUTTER: f32 = 1.0 / 125.0;
+/// The f-number assumed for the exposure of a camera without an aperture
+const DEFAULT_F_STOP: f32 = 16.0;
+/// Luminous efficacy used to convert radiance back to luminance
+const LUMINOUS_EFFICACY: f32 = 683.0;
+
+#[derive(Clone, Copy)]
+pub enum Fisheye {
+    Equidistant,
+    Equisolid,
+    Stereographic,
+}
+
+#[derive(Clone, Copy)]
+enum Aperture {
+    FStop(f32),
+    Radius(f32),
+}
 
 pub struct CameraBuilder {
+    kind: u32,
     transform: Mat4,
     yfov: f32,
     aspect_ratio: Option<f32>,
+    /// Half the width and height of the view of an orthographic camera
+    magnification: (f32, f32),
+    /// The distance between the eyes of a stereo panorama
+    interpupillary_distance: f32,
+    /// The field of view of a fisheye across the shorter side of the image
+    fisheye_fov: f32,
+    /// Radial coefficients k1, k2 and k3 and tangential coefficients p1 and p2 of the
+    /// Brown-Conrady distortion model
+    radial_distortion: Vec3,
+    tangential_distortion: Vec2,
+    aperture: Option<Aperture>,
+    focus_distance: f32,
+    /// Whether the focus distance was given, without which an f-stop only sets the exposure
+    explicit_focus: bool,
+    aperture_blades: u32,
+    aperture_rotation: f32,
+    ev100: Option<f32>,
+    /// ISO sensitivity and shutter time in seconds
+    sensitivity: Option<(f32, f32)>,
+    vignetting: bool,
 }
 
 impl CameraBuilder {
@@ -27,49 +89,368 @@ impl CameraBuilder {
         self
     }
 
+    /// Places the camera at `eye`, looking at `target`
+    pub fn look_at(&mut self, eye: Vec3, target: Vec3, up: Vec3) -> &mut CameraBuilder {
+        self.transform = Mat4::look_at_rh(eye, target, up).inverse();
+        self
+    }
+
+    /// Places the camera in front of a bounding box, slightly above it, so that the whole box is
+    /// in view and in focus at its center, with the field of view the view settings will give
+    pub fn frame(&mut self, bounds: &Aabb3, config: &Config) -> &mut CameraBuilder {
+        let Size { width, height } = config.size;
+        let aspect_ratio = config
+            .aspect_ratio
+            .or(self.aspect_ratio)
+            .unwrap_or(width as f32 / height as f32);
+        let yfov = config.vfov.map_or(self.yfov, f32::to_radians);
+        let half_yfov = yfov / 2.0;
+        let half_xfov = (aspect_ratio * half_yfov.tan()).atan();
+        let radius = (bounds.max - bounds.min).length().max(f32::EPSILON) / 2.0;
+        let distance = radius / half_yfov.min(half_xfov).sin();
+
+        let center = bounds.centroid();
+        let eye = center + Vec3::new(0.0, 0.5, 1.0).normalize() * distance;
+        self.focus_distance = distance;
+        self.look_at(eye, center, Vec3::Y)
+    }
+
+    /// Makes the camera orthographic, viewing an area of `2 * xmag` by `2 * ymag`
+    pub fn orthographic(&mut self, xmag: f32, ymag: f32) -> &mut CameraBuilder {
+        self.kind = CAMERA_ORTHOGRAPHIC;
+        self.magnification = (xmag, ymag);
+        self
+    }
+
+    /// Makes the camera see all around it, mapping longitude and latitude to the image
+    pub fn equirectangular(&mut self) -> &mut CameraBuilder {
+        self.kind = CAMERA_EQUIRECTANGULAR;
+        self
+    }
+
+    /// Makes the camera an omni-directional stereo panorama, with the left eye in the top half of
+    /// the image and the right eye in the bottom half
+    pub fn stereo_panorama(&mut self, interpupillary_distance: f32) -> &mut CameraBuilder {
+        self.kind = CAMERA_STEREO_PANORAMA;
+        self.interpupillary_distance = interpupillary_distance;
+        self
+    }
+
+    /// Makes the camera a fisheye with the given field of view in radians
+    pub fn fisheye(&mut self, fisheye: Fisheye, fov: f32) -> &mut CameraBuilder {
+        self.kind = match fisheye {
+            Fisheye::Equidistant => CAMERA_EQUIDISTANT,
+            Fisheye::Equisolid => CAMERA_EQUISOLID,
+            Fisheye::Stereographic => CAMERA_STEREOGRAPHIC,
+        };
+        self.fisheye_fov = fov.clamp(f32::EPSILON, 2.0 * PI);
+        self
+    }
+
+    /// Distorts the image of a perspective camera with the Brown-Conrady model, whose
+    /// coefficients follow the convention of OpenCV with image coordinates pointing down
+    pub fn distortion(&mut self, radial: Vec3, tangential: Vec2) -> &mut CameraBuilder {
+        self.radial_distortion = radial;
+        self.tangential_distortion = tangential;
+        self
+    }
+
+    /// Sets the aperture by its f-number, assuming a full frame sensor and a scene in meters
+    pub fn f_stop(&mut self, f_stop: f32) -> &mut CameraBuilder {
+        self.aperture = Some(Aperture::FStop(f_stop));
+        self
+    }
+
+    pub fn aperture_radius(&mut self, aperture_radius: f32) -> &mut CameraBuilder {
+        self.aperture = Some(Aperture::Radius(aperture_radius));
+        self
+    }
+
+    pub fn focus_distance(&mut self, focus_distance: f32) -> &mut CameraBuilder {
+        self.focus_distance = focus_distance;
+        self.explicit_focus = true;
+        self
+    }
+
+    /// Shapes the aperture as a regular polygon with the given number of blades, rotated by an
+    /// angle in radians, instead of a disk
+    pub fn aperture_blades(&mut self, blades: u32, rotation: f32) -> &mut CameraBuilder {
+        self.aperture_blades = blades;
+        self.aperture_rotation = rotation;
+        self
+    }
+
+    /// Exposes the image for an exposure value at ISO 100
+    pub fn ev100(&mut self, ev100: f32) -> &mut CameraBuilder {
+        self.ev100 = Some(ev100);
+        self
+    }
+
+    /// Exposes the image for an ISO sensitivity and a shutter time in seconds, together with the
+    /// f-number of the aperture
+    pub fn sensitivity(&mut self, iso: f32, shutter: f32) -> &mut CameraBuilder {
+        self.sensitivity = Some((iso, shutter));
+        self
+    }
+
+    /// Darkens the image towards its edges by the cos⁴ law of natural vignetting
+    pub fn vignetting(&mut self, vignetting: bool) -> &mut CameraBuilder {
+        self.vignetting = vignetting;
+        self
+    }
+
+    /// Applies the exposure settings of the command line
+    pub fn exposure(&mut self, config: &Config) -> &mut CameraBuilder {
+        if let Some(ev100) = config.ev100 {
+            self.ev100(ev100);
+        } else if config.iso.is_some() || config.shutter.is_some() {
+            self.sensitivity(
+                config.iso.unwrap_or(DEFAULT_ISO),
+                config.shutter.unwrap_or(DEFAULT_SHUTTER),
+            );
+        }
+        self.vignetting(config.vignetting)
+    }
+
+    /// Applies the view settings of the command line, replacing the ones of the scene. Fails if
+    /// they don't give a view direction.
+    pub fn view(&mut self, config: &Config) -> Result<&mut CameraBuilder> {
+        let eye = config
+            .camera_position
+            .unwrap_or_else(|| self.transform.transform_point3(Vec3::ZERO));
+        if config.look_at.is_some() || config.camera_up.is_some() {
+            let forward = self.transform.transform_vector3(Vec3::NEG_Z);
+            let target = config.look_at.unwrap_or(eye + forward);
+            let up = config.camera_up.unwrap_or(Vec3::Y);
+            if target == eye {
+                bail!("The look-at target {target} is at the camera position");
+            }
+            if (target - eye).cross(up).length_squared() == 0.0 {
+                bail!("The camera up vector {up} is parallel to the view direction");
+            }
+            self.look_at(eye, target, up);
+        } else {
+            self.transform.w_axis = eye.extend(1.0);
+        }
+
+        match config.projection {
+            Some(Projection::Equirectangular) => {
+                self.equirectangular();
+            }
+            Some(Projection::Ods) => {
+                self.stereo_panorama(config.ipd);
+            }
+            Some(Projection::Equidistant) => {
+                self.fisheye(Fisheye::Equidistant, config.fisheye_fov.to_radians());
+            }
+            Some(Projection::Equisolid) => {
+                self.fisheye(Fisheye::Equisolid, config.fisheye_fov.to_radians());
+            }
+            Some(Projection::Stereographic) => {
+                self.fisheye(Fisheye::Stereographic, config.fisheye_fov.to_radians());
+            }
+            None => {}
+        }
+        if !config.distortion.is_empty() {
+            let coefficient = |i: usize| config.distortion.get(i).copied().unwrap_or(0.0);
+            self.distortion(
+                Vec3::new(coefficient(0), coefficient(1), coefficient(4)),
+                Vec2::new(coefficient(2), coefficient(3)),
+            );
+        }
+        if let Some(vfov) = config.vfov {
+            self.yfov(vfov.to_radians());
+        }
+        if let Some(aspect_ratio) = config.aspect_ratio {
+            self.aspect_ratio(aspect_ratio);
+        }
+        Ok(self)
+    }
+
+    /// Applies the lens settings, the ones of the command line taking precedence
+    pub fn lens(&mut self, lens: &Lens, config: &Config) -> &mut CameraBuilder {
+        if let Some(aperture_radius) = config.aperture_radius {
+            self.aperture_radius(aperture_radius);
+        } else if let Some(f_stop) = config.f_stop {
+            self.f_stop(f_stop);
+        } else if let Some(aperture_radius) = lens.aperture_radius {
+            self.aperture_radius(aperture_radius);
+        } else if let Some(f_stop) = lens.f_stop {
+            self.f_stop(f_stop);
+        }
+
+        if let Some(focus_distance) = config.focus_distance.or(lens.focus_distance) {
+            self.focus_distance(focus_distance);
+        }
+        if let Some(blades) = config.aperture_blades.or(lens.aperture_blades) {
+            let rotation = config.aperture_rotation.or(lens.aperture_rotation);
+            self.aperture_blades(blades, rotation.unwrap_or(0.0).to_radians());
+        }
+        self
+    }
+
     pub fn build(&self, width: u32, height: u32) -> Camera {
         let aspect_ratio = if let Some(aspect_ratio) = self.aspect_ratio {
             aspect_ratio
         } else {
             width as f32 / height as f32
         };
-        let viewport_height = 2.0 * (self.yfov / 2.0).tan();
-        let viewport_width = aspect_ratio * viewport_height;
+        let distorted =
+            self.radial_distortion != Vec3::ZERO || self.tangential_distortion != Vec2::ZERO;
+        let kind = match self.kind {
+            CAMERA_PERSPECTIVE if distorted => CAMERA_BROWN_CONRADY,
+            kind => {
+                if distorted {
+                    warn!("Lens distortion is only supported by perspective cameras");
+                }
+                kind
+            }
+        };
+
+        let (pix_orig, pix_dx, pix_dy) = match kind {
+            CAMERA_EQUIRECTANGULAR | CAMERA_STEREO_PANORAMA => {
+                // The position on the image in the range [0, 1]
+                let pix_dx = Vec3::new(1.0 / width as f32, 0.0, 0.0);
+                let pix_dy = Vec3::new(0.0, 1.0 / height as f32, 0.0);
+                (0.5 * pix_dx + 0.5 * pix_dy, pix_dx, pix_dy)
+            }
+            CAMERA_EQUIDISTANT | CAMERA_EQUISOLID | CAMERA_STEREOGRAPHIC => {
+                // The position relative to the image center, with the shorter side spanning [-1, 1]
+                let scale = 2.0 / width.min(height) as f32;
+                let pix_dx = Vec3::new(scale, 0.0, 0.0);
+                let pix_dy = Vec3::new(0.0, -scale, 0.0);
+                let pix_orig =
+                    -(width as f32 - 1.0) / 2.0 * pix_dx - (height as f32 - 1.0) / 2.0 * pix_dy;
+                (pix_orig, pix_dx, pix_dy)
+            }
+            kind => {
+                let (viewport_width, viewport_height) = if kind == CAMERA_ORTHOGRAPHIC {
+                    (2.0 * self.magnification.0, 2.0 * self.magnification.1)
+                } else {
+                    let viewport_height = 2.0 * (self.yfov / 2.0).tan();
+                    (aspect_ratio * viewport_height, viewport_height)
+                };
+
+                let pix_du = Vec3::new(viewport_width, 0.0, 0.0);
+                let pix_dv = Vec3::new(0.0, -viewport_height, 0.0);
+                let pix_dx = pix_du / width as f32;
+                let pix_dy = pix_dv / height as f32;
+                let pix_orig = Vec3::new(0.0, 0.0, -1.0) - 0.5 * pix_du - 0.5 * pix_dv;
+                (pix_orig, pix_dx, pix_dy)
+            }
+        };
+
+        let lens_radius = match self.aperture {
+            Some(Aperture::Radius(radius)) => radius,
+            Some(Aperture::FStop(_)) if !self.explicit_focus => 0.0,
+            Some(_)
+                if !matches!(
+                    kind,
+                    CAMERA_PERSPECTIVE | CAMERA_ORTHOGRAPHIC | CAMERA_BROWN_CONRADY
+                ) =>
+            {
+                warn!("Depth of field is not supported by panoramic and fisheye cameras");
+                0.0
+            }
+            Some(Aperture::FStop(_)) if kind == CAMERA_ORTHOGRAPHIC => {
+                warn!("An f-stop has no effect on orthographic cameras, use an aperture radius");
+                0.0
+            }
+            Some(Aperture::FStop(f_stop)) => self.focal_length() / (2.0 * f_stop),
+            None => 0.0,
+        };
 
-        let pix_du = Vec3::new(viewport_width, 0.0, 0.0);
-        let pix_dv = Vec3::new(0.0, -viewport_height, 0.0);
-        let pix_dx = pix_du / width as f32;
-        let pix_dy = pix_dv / height as f32;
-        let pix_orig = Vec3::new(0.0, 0.0, -1.0) - 0.5 * pix_du - 0.5 * pix_dv;
+        // Saturation based exposure, mapping the luminance that saturates the sensor to one
+        let ev100 = self.ev100.or(self.sensitivity.map(|(iso, shutter)| {
+            let f_stop = match self.aperture {
+                Some(Aperture::FStop(f_stop)) => f_stop,
+                Some(Aperture::Radius(radius)) if radius > 0.0 => {
+                    self.focal_length() / (2.0 * radius)
+                }
+                _ => DEFAULT_F_STOP,
+            };
+            (f_stop * f_stop / shutter * 100.0 / iso).log2()
+        }));
+        let exposure = ev100.map_or(1.0, |ev100| LUMINOUS_EFFICACY / (1.2 * ev100.exp2()));
 
         Camera {
+            kind,
             transform: self.transform,
             pix_orig,
             pix_dx,
             pix_dy,
+            lens_radius,
+            focus_distance: self.focus_distance,
+            aperture_blades: self.aperture_blades,
+            aperture_rotation: self.aperture_rotation,
+            interpupillary_distance: self.interpupillary_distance,
+            max_angle: self.fisheye_fov / 2.0,
+            radial_distortion: self.radial_distortion,
+            tangential_distortion: self.tangential_distortion,
+            vignetting: self.vignetting as u32,
+            exposure,
         }
     }
+
+    /// The focal length in meters of a full frame camera with the vertical field of view
+    fn focal_length(&self) -> f32 {
+        0.5 * SENSOR_HEIGHT / (self.yfov / 2.0).tan()
+    }
 }
 
 impl Default for CameraBuilder {
     fn default() -> Self {
         Self {
+            kind: CAMERA_PERSPECTIVE,
             transform: Mat4::IDENTITY,
             yfov: 90.0_f32.to_radians(),
             aspect_ratio: None,
+            magnification: (1.0, 1.0),
+            interpupillary_distance: 0.0,
+            fisheye_fov: PI,
+            radial_distortion: Vec3::ZERO,
+            tangential_distortion: Vec2::ZERO,
+            ev100: None,
+            sensitivity: None,
+            vignetting: false,
+            aperture: None,
+            focus_distance: 1.0,
+            explicit_focus: false,
+            aperture_blades: 0,
+            aperture_rotation: 0.0,
         }
     }
 }
 
 #[derive(Default, ShaderType)]
 pub struct Camera {
+    kind: u32,
     transform: Mat4,
     pix_orig: Vec3,
     pix_dx: Vec3,
     pix_dy: Vec3,
+    /// The radius of the thin lens, zero for a pinhole camera
+    lens_radius: f32,
+    /// The distance along the view direction that is in focus
+    focus_distance: f32,
+    /// The number of sides of a polygonal aperture, or zero for a circular one
+    aperture_blades: u32,
+    aperture_rotation: f32,
+    interpupillary_distance: f32,
+    /// The angle from the view direction at the edge of a fisheye
+    max_angle: f32,
+    radial_distortion: Vec3,
+    tangential_distortion: Vec2,
+    vignetting: u32,
+    /// The factor scaling radiance before tone mapping
+    exposure: f32,
 }
 
 impl Camera {
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
     /// Returns a new camera which is a rotated version of self.
     ///
     /// ## Parameters
@@ -88,9 +469,58 @@ impl Camera {
 
         Camera {
             transform: new_transform,
-            pix_orig: self.pix_orig,
-            pix_dx: self.pix_dx,
-            pix_dy: self.pix_dy,
+            ..*self
         }
     }
 }
+
+/// Depth of field settings read from the extras of a glTF camera, like
+/// `{"fStop": 2.8, "focusDistance": 1.5, "apertureBlades": 6}`
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lens {
+    f_stop: Option<f32>,
+    aperture_radius: Option<f32>,
+    focus_distance: Option<f32>,
+    aperture_blades: Option<u32>,
+    /// The rotation of a polygonal aperture, in degrees
+    aperture_rotation: Option<f32>,
+}
+
+impl Lens {
+    pub fn from_extras(extras: &Extras) -> Self {
+        extras
+            .as_ref()
+            .and_then(|extras| serde_json::from_str(extras.get()).ok())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn parse(args: &[&str]) -> Result<Config, clap::Error> {
+        Config::try_parse_from(["prisma"].iter().chain(args).chain(&["scene.glb"]))
+    }
+
+    #[test]
+    fn view_needs_a_direction() {
+        let config = parse(&["--camera-position", "1,2,3", "--look-at", "1,2,3"]).unwrap();
+        assert!(CameraBuilder::new().view(&config).is_err());
+
+        let config = parse(&["--camera-position", "0,0,0", "--look-at", "0,-1,0"]).unwrap();
+        assert!(CameraBuilder::new().view(&config).is_err());
+
+        let config = parse(&["--camera-position", "0,0,0", "--look-at", "0,0,-1"]).unwrap();
+        assert!(CameraBuilder::new().view(&config).is_ok());
+    }
+
+    #[test]
+    fn f_stop_is_positive() {
+        assert!(parse(&["--f-stop", "0"]).is_err());
+        assert!(parse(&["--f-stop", "-2"]).is_err());
+        assert_eq!(parse(&["--f-stop", "2.8"]).unwrap().f_stop, Some(2.8));
+    }
+}
@@ -0,0 +1,64 @@
+//! A variant of [`gltf::import`] that also decodes the KTX2 images referenced by
+//! `KHR_texture_basisu`, which the gltf crate itself can't read.
+use crate::textures::decode_ktx2;
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use gltf::{buffer, image, Document, Gltf};
+use std::path::Path;
+
+const KTX2_MIME_TYPE: &str = "image/ktx2";
+
+pub fn import(path: &str) -> Result<(Document, Vec<buffer::Data>, Vec<image::Data>)> {
+    let base = Path::new(path).parent().unwrap_or_else(|| Path::new("./"));
+    let Gltf { document, blob } = Gltf::open(path)?;
+    let buffers = gltf::import_buffers(&document, Some(base), blob)?;
+
+    let mut images = Vec::new();
+    for image in document.images() {
+        let data = match ktx2_bytes(image.source(), base, &buffers)? {
+            Some(bytes) => {
+                let decoded = decode_ktx2(&bytes)
+                    .map_err(|err| anyhow!("Failed to decode image {}: {err}", image.index()))?;
+                image::Data {
+                    width: decoded.width(),
+                    height: decoded.height(),
+                    format: image::Format::R8G8B8A8,
+                    pixels: decoded.into_raw(),
+                }
+            }
+            None => image::Data::from_source(image.source(), Some(base), &buffers)?,
+        };
+        images.push(data);
+    }
+
+    Ok((document, buffers, images))
+}
+
+/// Returns the encoded bytes of `source` if it is a KTX2 image, or `None` for other formats.
+fn ktx2_bytes(
+    source: image::Source,
+    base: &Path,
+    buffers: &[buffer::Data],
+) -> Result<Option<Vec<u8>>> {
+    match source {
+        image::Source::View { view, mime_type } if mime_type == KTX2_MIME_TYPE => {
+            let buffer = &buffers[view.buffer().index()];
+            Ok(Some(
+                buffer[view.offset()..view.offset() + view.length()].to_vec(),
+            ))
+        }
+        image::Source::Uri { uri, .. } if uri.starts_with("data:") => {
+            match uri.strip_prefix("data:image/ktx2;base64,") {
+                Some(data) => Ok(Some(STANDARD.decode(data)?)),
+                None => Ok(None),
+            }
+        }
+        image::Source::Uri { uri, mime_type }
+            if mime_type == Some(KTX2_MIME_TYPE) || uri.ends_with(".ktx2") =>
+        {
+            let path = uri.strip_prefix("file://").unwrap_or(uri);
+            Ok(Some(std::fs::read(base.join(path))?))
+        }
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,271 @@
+use anyhow::{bail, Result};
+use encase::ShaderType;
+use glam::{Mat4, Vec3};
+use gltf::{
+    buffer,
+    khr_lights_punctual::{Kind, Light as GltfLight},
+    Mesh, Node,
+};
+use serde::Deserialize;
+
+/// Luminous efficacy used to convert the photometric glTF units to radiance, like the sky
+const LUMINOUS_EFFICACY: f32 = 683.0;
+
+const LIGHT_DIRECTIONAL: u32 = 0;
+const LIGHT_POINT: u32 = 1;
+const LIGHT_SPOT: u32 = 2;
+const LIGHT_RECT: u32 = 3;
+const LIGHT_DISK: u32 = 4;
+const LIGHT_SPHERE: u32 = 5;
+const LIGHT_PORTAL: u32 = 6;
+
+#[derive(ShaderType)]
+pub struct Light {
+    kind: u32,
+    /// Color times intensity, in W/sr for point and spot lights, W/m² for directional lights and
+    /// W/(sr m²) for area lights
+    intensity: Vec3,
+    /// The position of point and spot lights or the center of area lights
+    position: Vec3,
+    /// The direction the light shines in, which is the emitting side of rectangles and disks
+    direction: Vec3,
+    /// The edges of rectangle lights and portals
+    axis_u: Vec3,
+    axis_v: Vec3,
+    /// The radius of disk and sphere lights
+    radius: f32,
+    /// The distance at which the light reaches zero, or zero for an infinite range
+    range: f32,
+    /// Scale and offset of the cosine to the spot direction giving the cone falloff
+    angle_scale: f32,
+    angle_offset: f32,
+}
+
+#[derive(Default)]
+pub struct Lights {
+    registry: Vec<Light>,
+}
+
+impl Lights {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.registry.len()
+    }
+
+    /// Adds a `KHR_lights_punctual` light placed by the transform of its node
+    pub fn add(&mut self, light: &GltfLight, transform: &Mat4) {
+        let intensity = Vec3::from_array(light.color()) * light.intensity() / LUMINOUS_EFFICACY;
+        let (kind, angle_scale, angle_offset) = match light.kind() {
+            Kind::Directional => (LIGHT_DIRECTIONAL, 0.0, 1.0),
+            Kind::Point => (LIGHT_POINT, 0.0, 1.0),
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => {
+                let cos_inner = inner_cone_angle.cos();
+                let cos_outer = outer_cone_angle.cos();
+                let angle_scale = 1.0 / (cos_inner - cos_outer).max(0.001);
+                (LIGHT_SPOT, angle_scale, -cos_outer * angle_scale)
+            }
+        };
+
+        self.registry.push(Light {
+            kind,
+            intensity,
+            position: transform.transform_point3(Vec3::ZERO),
+            direction: transform.transform_vector3(Vec3::NEG_Z).normalize(),
+            axis_u: Vec3::ZERO,
+            axis_v: Vec3::ZERO,
+            radius: 0.0,
+            range: light.range().unwrap_or(0.0),
+            angle_scale,
+            angle_offset,
+        });
+    }
+
+    /// Adds a portal spanning the bounding rectangle of a planar mesh, through which the
+    /// environment map is sampled
+    pub fn add_portal(
+        &mut self,
+        mesh: &Mesh,
+        buffers: &[buffer::Data],
+        transform: &Mat4,
+    ) -> Result<()> {
+        let mut positions = Vec::new();
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            if let Some(iter) = reader.read_positions() {
+                positions.extend(
+                    iter.map(|position| transform.transform_point3(Vec3::from_array(position))),
+                );
+            }
+        }
+        if positions.len() < 3 {
+            bail!("Portal mesh {} has no faces", mesh.index());
+        }
+
+        let u = (positions[1] - positions[0]).normalize_or_zero();
+        let normal = u.cross(positions[2] - positions[0]).normalize_or_zero();
+        if normal == Vec3::ZERO {
+            bail!("Portal mesh {} is degenerate", mesh.index());
+        }
+        let v = normal.cross(u);
+
+        let (mut min, mut max) = (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY));
+        for position in &positions {
+            let local = Vec3::new(position.dot(u), position.dot(v), position.dot(normal));
+            min = min.min(local);
+            max = max.max(local);
+        }
+        let center = (min + max) * 0.5;
+
+        self.registry.push(Light {
+            kind: LIGHT_PORTAL,
+            intensity: Vec3::ZERO,
+            position: center.x * u + center.y * v + center.z * normal,
+            direction: normal,
+            axis_u: u * (max.x - min.x),
+            axis_v: v * (max.y - min.y),
+            radius: 0.0,
+            range: 0.0,
+            angle_scale: 0.0,
+            angle_offset: 1.0,
+        });
+        Ok(())
+    }
+
+    /// Adds the area lights described by a JSON sidecar file
+    pub fn load_area_lights(&mut self, path: &str) -> Result<()> {
+        let descriptions: Vec<AreaLight> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        for (index, description) in descriptions.into_iter().enumerate() {
+            let name = match &description.name {
+                Some(name) => format!("Area light {name:?}"),
+                None => format!("Area light {index}"),
+            };
+            let mut light = Light {
+                kind: LIGHT_SPHERE,
+                intensity: Vec3::from_array(description.color) * description.luminance
+                    / LUMINOUS_EFFICACY,
+                position: Vec3::from_array(description.position),
+                direction: Vec3::NEG_Y,
+                axis_u: Vec3::ZERO,
+                axis_v: Vec3::ZERO,
+                radius: 0.0,
+                range: 0.0,
+                angle_scale: 0.0,
+                angle_offset: 1.0,
+            };
+
+            match description.shape {
+                Shape::Rect { normal, up, size } => {
+                    if size.iter().any(|&extent| extent <= 0.0) {
+                        bail!("{name} has a non-positive size {size:?}");
+                    }
+                    let normal = unit_normal(&name, normal)?;
+                    let u = Vec3::from_array(up).normalize_or_zero().cross(normal);
+                    if u.length() < 1e-3 {
+                        bail!("{name} has an up vector {up:?} parallel to its normal");
+                    }
+                    let u = u.normalize();
+                    light.kind = LIGHT_RECT;
+                    light.direction = normal;
+                    light.axis_u = u * size[0];
+                    light.axis_v = normal.cross(u) * size[1];
+                }
+                Shape::Disk { normal, radius } => {
+                    if radius <= 0.0 {
+                        bail!("{name} has a non-positive radius {radius}");
+                    }
+                    light.kind = LIGHT_DISK;
+                    light.direction = unit_normal(&name, normal)?;
+                    light.radius = radius;
+                }
+                Shape::Sphere { radius } => {
+                    if radius <= 0.0 {
+                        bail!("{name} has a non-positive radius {radius}");
+                    }
+                    light.radius = radius;
+                }
+            }
+            self.registry.push(light);
+        }
+        Ok(())
+    }
+
+    /// Returns the lights to upload, with a placeholder if there are none since storage buffers
+    /// can't be empty
+    pub fn buffer_data(&self) -> &[Light] {
+        if self.registry.is_empty() {
+            std::slice::from_ref(&PLACEHOLDER)
+        } else {
+            &self.registry
+        }
+    }
+}
+
+static PLACEHOLDER: Light = Light {
+    kind: LIGHT_POINT,
+    intensity: Vec3::ZERO,
+    position: Vec3::ZERO,
+    direction: Vec3::NEG_Z,
+    axis_u: Vec3::ZERO,
+    axis_v: Vec3::ZERO,
+    radius: 0.0,
+    range: 0.0,
+    angle_scale: 0.0,
+    angle_offset: 1.0,
+};
+
+/// Returns whether a node is marked as a portal by `"portal": true` in its extras
+pub fn is_portal(node: &Node) -> bool {
+    node.extras().as_ref().is_some_and(|extras| {
+        serde_json::from_str::<serde_json::Value>(extras.get())
+            .is_ok_and(|extras| extras["portal"] == serde_json::Value::Bool(true))
+    })
+}
+
+/// An emitter of a lights sidecar file, which holds an array of objects like
+/// `{"type": "sphere", "position": [0, 2, 0], "radius": 0.1, "luminance": 5000}`
+#[derive(Deserialize)]
+struct AreaLight {
+    /// Names the light in error messages
+    name: Option<String>,
+    #[serde(flatten)]
+    shape: Shape,
+    position: [f32; 3],
+    #[serde(default = "white")]
+    color: [f32; 3],
+    /// The emitted luminance, in cd/m²
+    luminance: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Shape {
+    Rect {
+        normal: [f32; 3],
+        up: [f32; 3],
+        size: [f32; 2],
+    },
+    Disk {
+        normal: [f32; 3],
+        radius: f32,
+    },
+    Sphere {
+        radius: f32,
+    },
+}
+
+fn unit_normal(name: &str, normal: [f32; 3]) -> Result<Vec3> {
+    match Vec3::from_array(normal).try_normalize() {
+        Some(normal) => Ok(normal),
+        None => bail!("{name} has a zero normal"),
+    }
+}
+
+fn white() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
@@ -0,0 +1,226 @@
+//! The Preetham et al. analytic daylight model, used in place of an environment map.
+//!
+//! Radiance is expressed as luminance in cd/m² divided by 683 lm/W, so that the sun and the sky
+//! keep their physical ratio. The sun is a disk of the real angular size whose color comes from
+//! Rayleigh and aerosol extinction along the air mass of its elevation.
+use crate::config::Config;
+use anyhow::{anyhow, bail, Result};
+use encase::ShaderType;
+use glam::Vec3;
+use std::f32::consts::{FRAC_PI_2, PI};
+
+/// Angular radius of the sun disk
+const SUN_ANGULAR_RADIUS: f32 = 0.00465;
+/// Extraterrestrial illuminance of the sun, in lux
+const SUN_ILLUMINANCE: f32 = 128_000.0;
+/// Luminous efficacy used to convert photometric values to radiance
+const LUMINOUS_EFFICACY: f32 = 683.0;
+/// Wavelengths in micrometers standing for the red, green and blue channels
+const WAVELENGTHS: Vec3 = Vec3::new(0.65, 0.57, 0.475);
+
+#[derive(Default, ShaderType)]
+pub struct Sky {
+    /// Nonzero if the sky replaces the environment map
+    enabled: u32,
+    /// Perez coefficients A to E for the Y, x and y channels
+    perez: [Vec3; 5],
+    /// Zenith values of Y, x and y divided by the Perez function at the zenith
+    zenith: Vec3,
+    sun_dir: Vec3,
+    sun_radiance: Vec3,
+    sun_cos_radius: f32,
+}
+
+impl Sky {
+    /// Creates a sky lit by a sun at the given elevation and azimuth in radians. The azimuth is
+    /// measured clockwise from north (-Z) towards east (+X).
+    pub fn new(turbidity: f32, elevation: f32, azimuth: f32) -> Self {
+        let t = turbidity.clamp(1.7, 10.0);
+        let sun_dir = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+
+        let perez = [
+            Vec3::new(
+                0.1787 * t - 1.4630,
+                -0.0193 * t - 0.2592,
+                -0.0167 * t - 0.2608,
+            ),
+            Vec3::new(
+                -0.3554 * t + 0.4275,
+                -0.0665 * t + 0.0008,
+                -0.0950 * t + 0.0092,
+            ),
+            Vec3::new(
+                -0.0227 * t + 5.3251,
+                -0.0004 * t + 0.2125,
+                -0.0079 * t + 0.2102,
+            ),
+            Vec3::new(
+                0.1206 * t - 2.5771,
+                -0.0641 * t - 0.8989,
+                -0.0441 * t - 1.6537,
+            ),
+            Vec3::new(
+                -0.0670 * t + 0.3703,
+                -0.0033 * t + 0.0452,
+                -0.0109 * t + 0.0529,
+            ),
+        ];
+
+        // The model isn't defined for a sun below the horizon, keep the sky of a setting sun
+        let theta = (FRAC_PI_2 - elevation).min(FRAC_PI_2);
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = zenith_chromaticity(
+            t,
+            theta,
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        );
+        let zenith_y = zenith_chromaticity(
+            t,
+            theta,
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        );
+        // Y is in kcd/m²
+        let zenith = Vec3::new(
+            zenith_luminance.max(0.0) * 1000.0 / LUMINOUS_EFFICACY,
+            zenith_x,
+            zenith_y,
+        ) / perez_function(&perez, 0.0, theta);
+
+        Self {
+            enabled: 1,
+            perez,
+            zenith,
+            sun_dir,
+            sun_radiance: sun_radiance(t, elevation),
+            sun_cos_radius: SUN_ANGULAR_RADIUS.cos(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let (elevation, azimuth) = match (config.latitude, config.longitude, &config.date_time) {
+            (Some(latitude), Some(longitude), Some(date_time)) => {
+                let (elevation, azimuth) =
+                    sun_position(latitude, longitude, julian_date(date_time)?);
+                log::info!(
+                    "Sun elevation {:.2}°, azimuth {:.2}°",
+                    elevation.to_degrees(),
+                    azimuth.to_degrees()
+                );
+                (elevation as f32, azimuth as f32)
+            }
+            _ => (
+                config.sun_elevation.to_radians(),
+                config.sun_azimuth.to_radians(),
+            ),
+        };
+        Ok(Self::new(config.turbidity, elevation, azimuth))
+    }
+}
+
+fn zenith_chromaticity(t: f32, theta: f32, t2: [f32; 4], t1: [f32; 4], t0: [f32; 4]) -> f32 {
+    let powers = [theta.powi(3), theta.powi(2), theta, 1.0];
+    let dot = |coefficients: [f32; 4]| -> f32 {
+        coefficients
+            .iter()
+            .zip(powers)
+            .map(|(coefficient, power)| coefficient * power)
+            .sum()
+    };
+    t * t * dot(t2) + t * dot(t1) + dot(t0)
+}
+
+/// The Perez sky luminance distribution for a zenith angle `theta` and an angle `gamma` from the
+/// sun, evaluated for all three channels
+fn perez_function(perez: &[Vec3; 5], theta: f32, gamma: f32) -> Vec3 {
+    let [a, b, c, d, e] = *perez;
+    let cos_theta = theta.cos().max(0.01);
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+/// Radiance of the sun disk after Rayleigh and aerosol extinction through the atmosphere
+fn sun_radiance(turbidity: f32, elevation: f32) -> Vec3 {
+    if elevation <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    // Kasten and Young's relative optical air mass
+    let zenith_degrees = 90.0 - elevation.to_degrees();
+    let air_mass = 1.0
+        / (zenith_degrees.to_radians().cos() + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+
+    // Rayleigh optical depth after Leckner and Ångström's aerosol turbidity formula
+    let rayleigh = 0.008735 * WAVELENGTHS.powf(-4.08);
+    let beta = 0.04608 * turbidity - 0.04586;
+    let aerosol = beta * WAVELENGTHS.powf(-1.3);
+    let transmittance = (-air_mass * (rayleigh + aerosol)).exp();
+
+    let solid_angle = 2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos());
+    transmittance * SUN_ILLUMINANCE / solid_angle / LUMINOUS_EFFICACY
+}
+
+/// Parses a UTC date and time like `2024-06-21T12:00` or `2024-06-21T12:00:30Z` into a Julian date
+fn julian_date(date_time: &str) -> Result<f64> {
+    let invalid = || anyhow!("Invalid date and time {date_time:?}, expected YYYY-MM-DDTHH:MM[:SS]");
+    let (date, time) = date_time.split_once(['T', ' ']).ok_or_else(invalid)?;
+    let date: Vec<i64> = date
+        .split('-')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let time: Vec<f64> = time
+        .trim_end_matches('Z')
+        .split(':')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let (&[year, month, day], &[hour, minute, ..]) = (&date[..], &time[..]) else {
+        return Err(invalid());
+    };
+    let second = time.get(2).copied().unwrap_or(0.0);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        bail!("Invalid date {date_time:?}");
+    }
+
+    // Fliegel and Van Flandern's Julian day number of the Gregorian calendar date
+    let a = (month - 14) / 12;
+    let day_number = (1461 * (year + 4800 + a)) / 4 + (367 * (month - 2 - 12 * a)) / 12
+        - (3 * ((year + 4900 + a) / 100)) / 4
+        + day
+        - 32075;
+    Ok(day_number as f64 - 0.5 + (hour + minute / 60.0 + second / 3600.0) / 24.0)
+}
+
+/// Returns the elevation and the azimuth (clockwise from north) of the sun in radians, using the
+/// low precision solar coordinates of the Astronomical Almanac
+fn sun_position(latitude: f64, longitude: f64, julian_date: f64) -> (f64, f64) {
+    let n = julian_date - 2451545.0;
+    let mean_longitude = (280.460 + 0.9856474 * n).to_radians();
+    let mean_anomaly = (357.528 + 0.9856003 * n).to_radians();
+    let ecliptic_longitude = mean_longitude
+        + (1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin()).to_radians();
+    let obliquity = (23.439 - 0.0000004 * n).to_radians();
+
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    let sidereal_time = (18.697374558 + 24.06570982441908 * n) * 15.0 + longitude;
+    let hour_angle = sidereal_time.to_radians() - right_ascension;
+
+    let latitude = latitude.to_radians();
+    let elevation = (latitude.sin() * declination.sin()
+        + latitude.cos() * declination.cos() * hour_angle.cos())
+    .asin();
+    let azimuth = (-hour_angle.sin())
+        .atan2(declination.tan() * latitude.cos() - latitude.sin() * hour_angle.cos());
+    (elevation, azimuth.rem_euclid(2.0 * std::f64::consts::PI))
+}
@@ -1,17 +1,25 @@
 use crate::render::RenderContext;
 use anyhow::Result;
 use gltf::image::Data;
-use image::ImageReader;
-use std::{cell::RefCell, error::Error, num::NonZeroU32, rc::Rc};
+use image::{imageops::FilterType, ImageReader, Rgba32FImage, RgbaImage};
+use log::info;
+use std::{cell::RefCell, error::Error, num::NonZeroU32, path::Path, rc::Rc};
 
+mod cube_map;
+mod etc1s;
+mod ktx2;
+mod pfm;
 mod texture;
 mod texture_hdr;
+mod uastc;
 
-use self::{texture::Texture, texture_hdr::TextureHdr};
+use self::{cube_map::Layout, texture::Texture, texture_hdr::TextureHdr};
+pub use self::{ktx2::decode as decode_ktx2, pfm::write as encode_pfm};
 
 pub struct Textures {
     context: Rc<RefCell<RenderContext>>,
     registry: Vec<Rc<dyn Texture2>>,
+    max_size: u32,
 }
 
 pub trait Texture2 {
@@ -20,15 +28,31 @@ pub trait Texture2 {
 }
 
 impl Textures {
-    pub fn new(context: Rc<RefCell<RenderContext>>) -> Self {
+    /// Creates an empty texture registry. Images wider or taller than `max_size` (or the device
+    /// limit, whichever is smaller) are downscaled before upload.
+    pub fn new(context: Rc<RefCell<RenderContext>>, max_size: u32) -> Self {
+        let device_limit = context.borrow().device().limits().max_texture_dimension_2d;
         Self {
             context,
             registry: Vec::new(),
+            max_size: max_size.min(device_limit),
         }
     }
 
+    /// Loads an environment map and uploads it as a lat-long panorama. `path` is either a single
+    /// lat-long or cube map cross image, or six comma-separated cube map faces ordered +X, -X, +Y,
+    /// -Y, +Z, -Z. OpenEXR and PFM files are accepted besides the formats of the image crate.
     pub fn load_texture_hdr(&mut self, path: &str) -> Result<u32> {
-        let image = ImageReader::open(path)?.decode()?.into_rgba32f();
+        let mut image = load_environment(path)?;
+        if let Some((width, height)) = fit_size(image.width(), image.height(), self.max_size) {
+            info!(
+                "Downscaling {path} from {}x{} to {width}x{height}",
+                image.width(),
+                image.height()
+            );
+            image = downscale_hdr(&image, width, height);
+        }
+
         let width = image.width();
         let height = image.height();
         self.registry.push(Rc::new(TextureHdr::new(
@@ -40,7 +64,20 @@ impl Textures {
         Ok(self.registry.len() as u32 - 1)
     }
 
-    pub fn add_texture(&mut self, image: &Data) -> u32 {
+    /// Uploads a single black texel standing in for an environment map that is never sampled
+    pub fn add_placeholder_hdr(&mut self) -> Result<u32> {
+        self.registry.push(Rc::new(TextureHdr::new(
+            self.context.clone(),
+            &[0.0, 0.0, 0.0, 1.0],
+            1,
+            1,
+        )?));
+        Ok(self.registry.len() as u32 - 1)
+    }
+
+    /// Uploads a glTF image, decoding its texels from sRGB unless `linear` is set, as for the
+    /// normal, occlusion and metallic-roughness data of materials
+    pub fn add_texture(&mut self, image: &Data, linear: bool) -> u32 {
         let num_pixels = (image.width * image.height) as usize;
         let mut data = Vec::with_capacity(num_pixels * 4);
 
@@ -64,11 +101,34 @@ impl Textures {
             _ => todo!(),
         }
 
+        let mut width = image.width;
+        let mut height = image.height;
+        if let Some((new_width, new_height)) = fit_size(width, height, self.max_size) {
+            info!(
+                "Downscaling texture {} from {width}x{height} to {new_width}x{new_height}",
+                self.registry.len()
+            );
+            let source = RgbaImage::from_raw(width, height, data).unwrap();
+            data = if linear {
+                image::imageops::resize(&source, new_width, new_height, FilterType::Lanczos3)
+            } else {
+                downscale_srgb(&source, new_width, new_height)
+            }
+            .into_raw();
+            (width, height) = (new_width, new_height);
+        }
+
+        let format = if linear {
+            wgpu::TextureFormat::Rgba8Unorm
+        } else {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        };
         self.registry.push(Rc::new(Texture::new(
             self.context.clone(),
             &data,
-            image.width,
-            image.height,
+            width,
+            height,
+            format,
         )));
         self.registry.len() as u32 - 1
     }
@@ -104,3 +164,123 @@ impl Textures {
         (bind_group_layout, bind_group)
     }
 }
+
+fn load_environment(path: &str) -> Result<Rgba32FImage> {
+    let paths: Vec<_> = path.split(',').collect();
+    if paths.len() == 6 {
+        let faces: Vec<_> = paths.into_iter().map(read_hdr).collect::<Result<_>>()?;
+        return cube_map::to_lat_long(&faces.try_into().unwrap());
+    }
+
+    let image = read_hdr(path)?;
+    match Layout::detect(image.width(), image.height()) {
+        Layout::LatLong => Ok(image),
+        layout => {
+            info!("Converting cube map cross {path} to a lat-long panorama");
+            cube_map::to_lat_long(&cube_map::split_cross(&image, layout)?)
+        }
+    }
+}
+
+fn read_hdr(path: &str) -> Result<Rgba32FImage> {
+    let is_pfm = Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pfm"));
+    if is_pfm {
+        pfm::read(&std::fs::read(path)?)
+    } else {
+        Ok(ImageReader::open(path)?.decode()?.into_rgba32f())
+    }
+}
+
+/// Returns the largest size with the same aspect ratio as `width`x`height` that fits in
+/// `max_size`, or `None` if the image already fits.
+fn fit_size(width: u32, height: u32, max_size: u32) -> Option<(u32, u32)> {
+    if width <= max_size && height <= max_size {
+        return None;
+    }
+
+    let scale = max_size as f64 / width.max(height) as f64;
+    let width = ((width as f64 * scale).round() as u32).clamp(1, max_size);
+    let height = ((height as f64 * scale).round() as u32).clamp(1, max_size);
+    Some((width, height))
+}
+
+/// Downscales an HDR image with a Lanczos filter, clamping the negative lobes that ringing around
+/// very bright texels (e.g. the sun) would otherwise produce.
+fn downscale_hdr(image: &Rgba32FImage, width: u32, height: u32) -> Rgba32FImage {
+    let mut image = image::imageops::resize(image, width, height, FilterType::Lanczos3);
+    for value in image.iter_mut() {
+        *value = value.max(0.0);
+    }
+    image
+}
+
+/// Downscales an sRGB image in linear light, so that filtering doesn't darken high contrast
+/// details. Alpha is already linear and is filtered as is.
+fn downscale_srgb(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let linear = Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        image::Rgba([
+            srgb_to_linear(r),
+            srgb_to_linear(g),
+            srgb_to_linear(b),
+            a as f32 / 255.0,
+        ])
+    });
+    let linear = downscale_hdr(&linear, width, height);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let [r, g, b, a] = linear.get_pixel(x, y).0;
+        let alpha = (a.min(1.0) * 255.0).round() as u8;
+        image::Rgba([
+            linear_to_srgb(r),
+            linear_to_srgb(g),
+            linear_to_srgb(b),
+            alpha,
+        ])
+    })
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trip() {
+        for value in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn downscale_srgb_averages_in_linear_light() {
+        let checkerboard = RgbaImage::from_fn(8, 8, |x, y| {
+            let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+            image::Rgba([value, value, value, 255])
+        });
+        let image = downscale_srgb(&checkerboard, 1, 1);
+        // Half of the light is 0.5 in linear space, which encodes to 188 rather than 128
+        let [r, g, b, a] = image.get_pixel(0, 0).0;
+        assert!((186..=190).contains(&r), "{r}");
+        assert_eq!((r, r, 255), (g, b, a));
+    }
+}
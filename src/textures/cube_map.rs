@@ -0,0 +1,163 @@
+//! Conversion of cube map environments to the lat-long panoramas sampled by `sample_panorama`.
+//!
+//! Faces are ordered +X, -X, +Y, -Y, +Z, -Z and oriented like OpenGL cube map faces stored from the
+//! top row down. The cross layouts are assembled from them with -X, +Z and +X in the middle row and
+//! +Y above and -Y below +Z. The horizontal cross continues the middle row with -Z, while the
+//! vertical cross places it below -Y, rotated by 180 degrees.
+use anyhow::{bail, Result};
+use glam::{Vec2, Vec3};
+use image::{imageops, Rgba, Rgba32FImage};
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    LatLong,
+    HorizontalCross,
+    VerticalCross,
+}
+
+impl Layout {
+    /// Guesses the layout of an environment map from its aspect ratio
+    pub fn detect(width: u32, height: u32) -> Self {
+        if width * 3 == height * 4 {
+            Self::HorizontalCross
+        } else if width * 4 == height * 3 {
+            Self::VerticalCross
+        } else {
+            Self::LatLong
+        }
+    }
+}
+
+/// Cuts the six faces out of a cross layout
+pub fn split_cross(image: &Rgba32FImage, layout: Layout) -> Result<[Rgba32FImage; 6]> {
+    let (size, positions) = match layout {
+        Layout::HorizontalCross => (
+            image.width() / 4,
+            [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)],
+        ),
+        Layout::VerticalCross => (
+            image.width() / 3,
+            [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (1, 3)],
+        ),
+        Layout::LatLong => bail!("A lat-long panorama is not a cube map cross"),
+    };
+
+    let mut faces = positions
+        .map(|(col, row)| imageops::crop_imm(image, col * size, row * size, size, size).to_image());
+    if layout == Layout::VerticalCross {
+        imageops::rotate180_in_place(&mut faces[5]);
+    }
+    Ok(faces)
+}
+
+/// Resamples six square cube map faces into a lat-long panorama twice as wide as it is tall
+pub fn to_lat_long(faces: &[Rgba32FImage; 6]) -> Result<Rgba32FImage> {
+    let size = faces[0].width();
+    if faces
+        .iter()
+        .any(|face| face.width() != size || face.height() != size)
+    {
+        bail!("Cube map faces must be square and of the same size");
+    }
+
+    let (width, height) = (size * 4, size * 2);
+    Ok(Rgba32FImage::from_fn(width, height, |x, y| {
+        // Inverse of the mapping in sample_panorama
+        let phi = (x as f32 + 0.5) / width as f32 * 2.0 * PI - PI;
+        let theta = (1.0 - (y as f32 + 0.5) / height as f32) * PI;
+        let dir = Vec3::new(
+            theta.sin() * phi.cos(),
+            -theta.cos(),
+            -theta.sin() * phi.sin(),
+        );
+
+        let (face, uv) = cube_uv(dir);
+        sample_bilinear(&faces[face], uv * size as f32 - 0.5)
+    }))
+}
+
+/// Returns the face a direction points at and the position on it in the range [0, 1]
+fn cube_uv(dir: Vec3) -> (usize, Vec2) {
+    let abs = dir.abs();
+    let (face, major, uv) = if abs.x >= abs.y && abs.x >= abs.z {
+        if dir.x > 0.0 {
+            (0, abs.x, Vec2::new(-dir.z, -dir.y))
+        } else {
+            (1, abs.x, Vec2::new(dir.z, -dir.y))
+        }
+    } else if abs.y >= abs.z {
+        if dir.y > 0.0 {
+            (2, abs.y, Vec2::new(dir.x, dir.z))
+        } else {
+            (3, abs.y, Vec2::new(dir.x, -dir.z))
+        }
+    } else if dir.z > 0.0 {
+        (4, abs.z, Vec2::new(dir.x, -dir.y))
+    } else {
+        (5, abs.z, Vec2::new(-dir.x, -dir.y))
+    };
+    (face, (uv / major + 1.0) * 0.5)
+}
+
+fn sample_bilinear(image: &Rgba32FImage, pos: Vec2) -> Rgba<f32> {
+    let max = Vec2::new(image.width() as f32 - 1.0, image.height() as f32 - 1.0);
+    let pos = pos.clamp(Vec2::ZERO, max);
+    let (x0, y0) = (pos.x as u32, pos.y as u32);
+    let (x1, y1) = ((x0 + 1).min(max.x as u32), (y0 + 1).min(max.y as u32));
+    let t = pos - Vec2::new(x0 as f32, y0 as f32);
+
+    let mut texel = [0.0; 4];
+    for (c, value) in texel.iter_mut().enumerate() {
+        let top = image[(x0, y0)][c] * (1.0 - t.x) + image[(x1, y0)][c] * t.x;
+        let bottom = image[(x0, y1)][c] * (1.0 - t.x) + image[(x1, y1)][c] * t.x;
+        *value = top * (1.0 - t.y) + bottom * t.y;
+    }
+    Rgba(texel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Looks up a direction in a lat-long panorama like `sample_panorama` does
+    fn sample_panorama(image: &Rgba32FImage, dir: Vec3) -> Rgba<f32> {
+        let theta = (-dir.y).acos();
+        let phi = (-dir.z).atan2(dir.x) + PI;
+        let u = phi / (2.0 * PI);
+        let v = theta / PI;
+        let x = (u * (image.width() - 1) as f32) as u32;
+        let y = ((1.0 - v) * (image.height() - 1) as f32) as u32;
+        image[(x, y)]
+    }
+
+    #[test]
+    fn faces_follow_opengl_convention() {
+        // Each quadrant of a face holds the index of the face and whether the quadrant is right
+        // of or below the center
+        let size = 16;
+        let faces = std::array::from_fn(|face| {
+            Rgba32FImage::from_fn(size, size, |x, y| {
+                let right = (x >= size / 2) as u32 as f32;
+                let bottom = (y >= size / 2) as u32 as f32;
+                Rgba([face as f32, right, bottom, 1.0])
+            })
+        });
+        let panorama = to_lat_long(&faces).unwrap();
+        assert_eq!(panorama.dimensions(), (4 * size, 2 * size));
+
+        // The expected quadrants follow from the major axis table of the OpenGL specification
+        let cases = [
+            (Vec3::new(1.0, 0.3, -0.4), [0.0, 1.0, 0.0]),
+            (Vec3::new(-1.0, -0.3, -0.4), [1.0, 0.0, 1.0]),
+            (Vec3::new(0.4, 1.0, 0.3), [2.0, 1.0, 1.0]),
+            (Vec3::new(-0.4, -1.0, 0.3), [3.0, 0.0, 0.0]),
+            (Vec3::new(0.4, 0.3, 1.0), [4.0, 1.0, 0.0]),
+            (Vec3::new(0.4, -0.3, -1.0), [5.0, 0.0, 1.0]),
+        ];
+        for (dir, [face, right, bottom]) in cases {
+            let texel = sample_panorama(&panorama, dir.normalize());
+            assert_eq!(texel, Rgba([face, right, bottom, 1.0]), "direction {dir}");
+        }
+    }
+}
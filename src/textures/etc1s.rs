@@ -0,0 +1,709 @@
+//! Decoder for ETC1S, the compact block format of Basis Universal, as stored in KTX2 containers
+//! with BasisLZ supercompression.
+//!
+//! The supercompression global data holds Huffman coded palettes of endpoints (a 5-bit color and
+//! an ETC1 intensity table) and selectors (the 2-bit texel indices of a 4x4 block) shared by all
+//! images. An image slice then codes an endpoint index for each block, predicted from its
+//! neighbors, and a selector index, either directly or through a small move-to-front history.
+//! Images with alpha have a second slice whose green channel is the alpha.
+use anyhow::{anyhow, bail, Result};
+
+/// The ETC1 intensity modifiers, ordered by selector
+#[rustfmt::skip]
+const INTENSITIES: [[i32; 4]; 8] = [
+    [-8, -2, 2, 8], [-17, -5, 5, 17], [-29, -9, 9, 29], [-42, -13, 13, 42],
+    [-60, -18, 18, 60], [-80, -24, 24, 80], [-106, -33, 33, 106], [-183, -47, 47, 183],
+];
+
+/// The order in which the code lengths of the code length alphabet are stored
+const CODE_LENGTH_ORDER: [usize; 21] = [
+    17, 18, 19, 20, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15, 16,
+];
+
+/// The endpoint predictor symbol that repeats the previous one
+const PRED_REPEAT_SYMBOL: u32 = 256;
+/// The selector history run length symbol that continues with a variable length count
+const SELECTOR_RUN_VLC_SYMBOL: u32 = 63;
+const MIN_RUN_LENGTH: u32 = 3;
+
+struct Endpoint {
+    color: [u8; 3],
+    intensity: usize,
+}
+
+/// The selectors of the 16 texels of a block in row-major order
+type Selectors = [u8; 16];
+
+/// Decodes the first image of a BasisLZ mip level into 8-bit RGBA pixels. Slice offsets in the
+/// global data are relative to `level`.
+pub fn decode(global_data: &[u8], level: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+    let field = |offset: usize, size: usize| -> Result<usize> {
+        let bytes = global_data
+            .get(offset..offset + size)
+            .ok_or_else(|| anyhow!("BasisLZ global data is truncated"))?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &b| value << 8 | b as usize))
+    };
+    let num_endpoints = field(0, 2)?;
+    let num_selectors = field(2, 2)?;
+    let lengths = [field(4, 4)?, field(8, 4)?, field(12, 4)?, field(16, 4)?];
+
+    // The image descriptions sit between the header and the codebooks, one per image of every
+    // mip level, layer and face, starting with the first image of the base level
+    let image_descs = global_data
+        .len()
+        .checked_sub(20 + lengths.iter().sum::<usize>())
+        .filter(|&size| size >= 20 && size % 20 == 0)
+        .ok_or_else(|| anyhow!("BasisLZ global data has invalid lengths"))?;
+    let mut offset = 20 + image_descs;
+    let [endpoints_data, selectors_data, tables_data, _] = lengths.map(|length| {
+        offset += length;
+        &global_data[offset - length..offset]
+    });
+    if num_endpoints == 0 || num_selectors == 0 {
+        bail!("BasisLZ global data has empty codebooks");
+    }
+
+    let endpoints = decode_endpoints(endpoints_data, num_endpoints)?;
+    let selectors = decode_selectors(selectors_data, num_selectors)?;
+    let tables = Tables::read(tables_data)?;
+
+    let blocks_x = width.div_ceil(4) as usize;
+    let blocks_y = height.div_ceil(4) as usize;
+    let slice = |offset: usize, length: usize| -> Result<Vec<(usize, usize)>> {
+        let data = level
+            .get(offset..offset + length)
+            .ok_or_else(|| anyhow!("ETC1S slice is outside of the mip level"))?;
+        tables.decode_slice(data, num_endpoints, num_selectors, blocks_x, blocks_y)
+    };
+    let rgb = slice(field(24, 4)?, field(28, 4)?)?;
+    let alpha = match field(36, 4)? {
+        0 => None,
+        length => Some(slice(field(32, 4)?, length)?),
+    };
+
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = vec![0; width * height * 4];
+    for (i, &(endpoint, selector)) in rgb.iter().enumerate() {
+        let texels = block_texels(&endpoints[endpoint], &selectors[selector]);
+        let alphas = alpha
+            .as_ref()
+            .map(|alpha| block_texels(&endpoints[alpha[i].0], &selectors[alpha[i].1]));
+
+        let (block_x, block_y) = (i % blocks_x * 4, i / blocks_x * 4);
+        for (j, texel) in texels.iter().enumerate() {
+            let (x, y) = (block_x + j % 4, block_y + j / 4);
+            if x < width && y < height {
+                let idx = (y * width + x) * 4;
+                pixels[idx..idx + 3].copy_from_slice(texel);
+                pixels[idx + 3] = alphas.map_or(0xFF, |alphas| alphas[j][1]);
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+/// The colors of a block, which offset the endpoint color by the intensity selected per texel
+fn block_texels(endpoint: &Endpoint, selectors: &Selectors) -> [[u8; 3]; 16] {
+    let colors = INTENSITIES[endpoint.intensity].map(|delta| {
+        endpoint
+            .color
+            .map(|c| (((c << 3 | c >> 2) as i32) + delta).clamp(0, 255) as u8)
+    });
+    selectors.map(|selector| colors[selector as usize])
+}
+
+/// Decodes the endpoint palette, whose colors and intensities are coded as deltas to the previous
+/// endpoint
+fn decode_endpoints(data: &[u8], count: usize) -> Result<Vec<Endpoint>> {
+    let mut reader = BitReader::new(data);
+    // The color delta tables are chosen by the previous value of each channel
+    let color_tables = [
+        Huffman::read(&mut reader)?,
+        Huffman::read(&mut reader)?,
+        Huffman::read(&mut reader)?,
+    ];
+    let intensity_table = Huffman::read(&mut reader)?;
+    let grayscale = reader.read(1) == 1;
+
+    let mut endpoints = Vec::with_capacity(count);
+    let mut color = [16; 3];
+    let mut intensity = 0;
+    for _ in 0..count {
+        intensity = (intensity + intensity_table.decode(&mut reader)? as usize) & 7;
+        for channel in color.iter_mut().take(if grayscale { 1 } else { 3 }) {
+            let table = match *channel {
+                0..=9 => &color_tables[0],
+                10..=21 => &color_tables[1],
+                _ => &color_tables[2],
+            };
+            *channel = ((*channel as u32 + table.decode(&mut reader)?) & 31) as u8;
+        }
+        if grayscale {
+            color = [color[0]; 3];
+        }
+        endpoints.push(Endpoint { color, intensity });
+    }
+    Ok(endpoints)
+}
+
+/// Decodes the selector palette, which is either raw or coded as the XOR of each row with the
+/// row of the previous selectors
+fn decode_selectors(data: &[u8], count: usize) -> Result<Vec<Selectors>> {
+    let mut reader = BitReader::new(data);
+    if reader.read(1) == 1 {
+        bail!("ETC1S images with a global selector codebook are not supported");
+    }
+    if reader.read(1) == 1 {
+        bail!("ETC1S images with a hybrid selector codebook are not supported");
+    }
+    let raw = reader.read(1) == 1;
+    let delta_table = if raw {
+        None
+    } else {
+        Some(Huffman::read(&mut reader)?)
+    };
+
+    let mut selectors = Vec::with_capacity(count);
+    let mut rows = [0; 4];
+    for i in 0..count {
+        let mut block = [0; 16];
+        for (y, row) in rows.iter_mut().enumerate() {
+            *row = match &delta_table {
+                Some(table) if i > 0 => table.decode(&mut reader)? ^ *row,
+                _ => reader.read(8),
+            };
+            for x in 0..4 {
+                block[y * 4 + x] = (*row >> (2 * x) & 3) as u8;
+            }
+        }
+        selectors.push(block);
+    }
+    Ok(selectors)
+}
+
+/// The Huffman tables of the image slices
+struct Tables {
+    endpoint_pred: Huffman,
+    endpoint_delta: Huffman,
+    selector: Huffman,
+    selector_run: Huffman,
+    history_size: usize,
+}
+
+/// The endpoint prediction state of a block in the current or previous row
+#[derive(Clone, Copy, Default)]
+struct BlockPred {
+    endpoint: usize,
+    /// The predictors of this block and its right neighbor in the next row
+    pred_bits: u32,
+}
+
+impl Tables {
+    fn read(data: &[u8]) -> Result<Self> {
+        let mut reader = BitReader::new(data);
+        let endpoint_pred = Huffman::read(&mut reader)?;
+        let endpoint_delta = Huffman::read(&mut reader)?;
+        let selector = Huffman::read(&mut reader)?;
+        let selector_run = Huffman::read(&mut reader)?;
+        let history_size = reader.read(13) as usize;
+        if history_size == 0 {
+            bail!("ETC1S selector history is empty");
+        }
+        Ok(Self {
+            endpoint_pred,
+            endpoint_delta,
+            selector,
+            selector_run,
+            history_size,
+        })
+    }
+
+    /// Decodes the `(endpoint, selector)` indices of every block of a slice in row-major order
+    fn decode_slice(
+        &self,
+        data: &[u8],
+        num_endpoints: usize,
+        num_selectors: usize,
+        blocks_x: usize,
+        blocks_y: usize,
+    ) -> Result<Vec<(usize, usize)>> {
+        let mut reader = BitReader::new(data);
+        let mut blocks = Vec::with_capacity(blocks_x * blocks_y);
+
+        // Every 2x2 group of blocks shares a symbol with 2 bits per block, picking the endpoint
+        // of the left, upper or upper left block or a delta to the previous endpoint
+        let mut preds = [
+            vec![BlockPred::default(); blocks_x],
+            vec![BlockPred::default(); blocks_x],
+        ];
+        let mut pred_bits = 0;
+        let mut prev_pred_symbol = 0;
+        let mut pred_repeat = 0;
+        let mut endpoint = 0;
+
+        let mut history = History::new(self.history_size);
+        let history_run_symbol = (num_selectors + self.history_size) as u32;
+        let mut selector_run = 0;
+
+        for y in 0..blocks_y {
+            let (cur, prev) = (y & 1, (y & 1) ^ 1);
+            for x in 0..blocks_x {
+                if x % 2 == 0 {
+                    if y % 2 == 0 {
+                        if pred_repeat > 0 {
+                            pred_repeat -= 1;
+                            pred_bits = prev_pred_symbol;
+                        } else {
+                            pred_bits = self.endpoint_pred.decode(&mut reader)?;
+                            if pred_bits == PRED_REPEAT_SYMBOL {
+                                pred_repeat = decode_vlc(&mut reader, 4) + MIN_RUN_LENGTH - 1;
+                                pred_bits = prev_pred_symbol;
+                            } else {
+                                prev_pred_symbol = pred_bits;
+                            }
+                        }
+                        preds[prev][x].pred_bits = pred_bits >> 4;
+                    } else {
+                        pred_bits = preds[cur][x].pred_bits;
+                    }
+                }
+
+                endpoint = match pred_bits & 3 {
+                    0 if x > 0 => endpoint,
+                    1 if y > 0 => preds[prev][x].endpoint,
+                    2 if x > 0 && y > 0 => preds[prev][x - 1].endpoint,
+                    3 => {
+                        let delta = self.endpoint_delta.decode(&mut reader)? as usize;
+                        if endpoint + delta >= num_endpoints {
+                            endpoint + delta - num_endpoints
+                        } else {
+                            endpoint + delta
+                        }
+                    }
+                    _ => bail!("ETC1S endpoint is predicted from outside of the image"),
+                };
+                if endpoint >= num_endpoints {
+                    bail!("ETC1S endpoint index is out of range");
+                }
+                pred_bits >>= 2;
+                preds[cur][x].endpoint = endpoint;
+
+                let symbol = if selector_run > 0 {
+                    selector_run -= 1;
+                    num_selectors as u32
+                } else {
+                    let symbol = self.selector.decode(&mut reader)?;
+                    if symbol == history_run_symbol {
+                        // A run of blocks reusing the most recent selector, starting with this one
+                        let run = match self.selector_run.decode(&mut reader)? {
+                            SELECTOR_RUN_VLC_SYMBOL => decode_vlc(&mut reader, 7),
+                            run => run,
+                        };
+                        selector_run = run + MIN_RUN_LENGTH - 1;
+                        num_selectors as u32
+                    } else {
+                        symbol
+                    }
+                };
+                let selector = match (symbol as usize).checked_sub(num_selectors) {
+                    None => {
+                        history.add(symbol as usize);
+                        symbol as usize
+                    }
+                    Some(index) => history.take(index)?,
+                };
+                blocks.push((endpoint, selector));
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+/// The recently used selector indices. New ones replace entries in the back half in turn, while
+/// reused ones move halfway to the front.
+struct History {
+    values: Vec<usize>,
+    next: usize,
+}
+
+impl History {
+    fn new(size: usize) -> Self {
+        Self {
+            values: vec![0; size],
+            next: size / 2,
+        }
+    }
+
+    fn add(&mut self, value: usize) {
+        self.values[self.next] = value;
+        self.next += 1;
+        if self.next == self.values.len() {
+            self.next = self.values.len() / 2;
+        }
+    }
+
+    fn take(&mut self, index: usize) -> Result<usize> {
+        let Some(&value) = self.values.get(index) else {
+            bail!("ETC1S selector history index is out of range");
+        };
+        self.values.swap(index, index / 2);
+        Ok(value)
+    }
+}
+
+/// Reads a number stored in chunks of `chunk_bits`, each followed by a bit telling whether more
+/// chunks follow
+fn decode_vlc(reader: &mut BitReader, chunk_bits: u32) -> u32 {
+    let mut value = 0;
+    let mut shift = 0;
+    while shift < 32 {
+        let chunk = reader.read(chunk_bits + 1);
+        value |= (chunk & ((1 << chunk_bits) - 1)) << shift;
+        shift += chunk_bits;
+        if chunk >> chunk_bits == 0 {
+            break;
+        }
+    }
+    value
+}
+
+/// Reads bits starting from the least significant bit of each byte. Like the Basis Universal
+/// transcoder, reading past the end returns zeros.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.data.get(self.pos / 8).copied().unwrap_or(0);
+            value |= ((byte >> (self.pos % 8)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+        value
+    }
+}
+
+/// A canonical Huffman code like the one of Deflate, stored with the first bit of each code in
+/// the least significant bit
+struct Huffman {
+    /// The number of codes of each length
+    counts: [u16; 17],
+    /// The symbols ordered by code length
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut counts = [0; 17];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1;
+        for &count in &counts[1..] {
+            left = 2 * left - count as i32;
+            if left < 0 {
+                bail!("Huffman code lengths are over-subscribed");
+            }
+        }
+
+        let mut symbols: Vec<_> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[symbol as usize] > 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+        Ok(Self { counts, symbols })
+    }
+
+    /// Reads a table stored as its code lengths, which are themselves Huffman coded with runs of
+    /// zeros and repeats
+    fn read(reader: &mut BitReader) -> Result<Self> {
+        let num_symbols = reader.read(14) as usize;
+        if num_symbols == 0 {
+            return Self::new(&[]);
+        }
+
+        let num_code_length_codes = reader.read(5) as usize;
+        if !(1..=CODE_LENGTH_ORDER.len()).contains(&num_code_length_codes) {
+            bail!("invalid number of Huffman code length codes");
+        }
+        let mut code_length_lengths = [0; 21];
+        for &symbol in &CODE_LENGTH_ORDER[..num_code_length_codes] {
+            code_length_lengths[symbol] = reader.read(3) as u8;
+        }
+        let code_lengths = Self::new(&code_length_lengths)?;
+
+        let mut lengths = vec![0; num_symbols];
+        let mut i = 0;
+        while i < num_symbols {
+            let (length, count) = match code_lengths.decode(reader)? {
+                length @ 0..=16 => (length as u8, 1),
+                17 => (0, reader.read(3) + 3),
+                18 => (0, reader.read(7) + 11),
+                symbol => {
+                    let prev = i.checked_sub(1).map_or(0, |prev| lengths[prev]);
+                    if prev == 0 {
+                        bail!("Huffman code length repeat without a previous length");
+                    }
+                    let count = if symbol == 19 {
+                        reader.read(2) + 3
+                    } else {
+                        reader.read(6) + 7
+                    };
+                    (prev, count)
+                }
+            };
+            let end = i + count as usize;
+            if end > num_symbols {
+                bail!("Huffman code lengths overrun the table");
+            }
+            lengths[i..end].fill(length);
+            i = end;
+        }
+        Self::new(&lengths)
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u32> {
+        // The first code and the index of its symbol for the current length
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= reader.read(1) as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as u32);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("invalid Huffman code")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs fields into bytes in the order they are read
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        len: usize,
+    }
+
+    impl BitWriter {
+        /// Writes `count` bits starting from the least significant one
+        fn bits(&mut self, value: u32, count: u32) -> &mut Self {
+            for i in 0..count {
+                if self.len % 8 == 0 {
+                    self.bytes.push(0);
+                }
+                self.bytes[self.len / 8] |= ((value >> i & 1) as u8) << (self.len % 8);
+                self.len += 1;
+            }
+            self
+        }
+
+        /// Writes a Huffman code starting from its most significant bit
+        fn code(&mut self, code: u32, length: u32) -> &mut Self {
+            for i in (0..length).rev() {
+                self.bits(code >> i & 1, 1);
+            }
+            self
+        }
+
+        /// Writes the definition of a Huffman table with the given code lengths. Each code length
+        /// is stored literally with a 5-bit code, so the table's canonical codes follow from
+        /// [`canonical_codes`].
+        fn table(&mut self, lengths: &[u8]) -> &mut Self {
+            self.bits(lengths.len() as u32, 14).bits(21, 5);
+            for _ in CODE_LENGTH_ORDER {
+                self.bits(5, 3);
+            }
+            for &length in lengths {
+                self.code(length as u32, 5);
+            }
+            self
+        }
+
+        /// Writes a table whose codes all have the same length, which makes the code of each
+        /// symbol the symbol itself
+        fn uniform_table(&mut self, num_symbols: usize) -> &mut Self {
+            self.table(&vec![code_length(num_symbols); num_symbols])
+        }
+
+        /// Writes a symbol of a table written with [`Self::uniform_table`]
+        fn symbol(&mut self, symbol: u32, num_symbols: usize) -> &mut Self {
+            self.code(symbol, code_length(num_symbols) as u32)
+        }
+    }
+
+    fn code_length(num_symbols: usize) -> u8 {
+        num_symbols.next_power_of_two().trailing_zeros().max(1) as u8
+    }
+
+    #[test]
+    fn canonical_codes() {
+        // Symbol 1 gets 0, symbol 0 gets 10 and symbols 2 and 3 get 110 and 111
+        let table = Huffman::new(&[2, 1, 3, 3]).unwrap();
+        let mut writer = BitWriter::default();
+        writer
+            .code(0b111, 3)
+            .code(0b0, 1)
+            .code(0b10, 2)
+            .code(0b110, 3);
+        let mut reader = BitReader::new(&writer.bytes);
+        let symbols = [0; 4].map(|_| table.decode(&mut reader).unwrap());
+        assert_eq!(symbols, [3, 1, 0, 2]);
+
+        assert!(Huffman::new(&[1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn table_with_runs() {
+        // Code lengths 2, five zeros, 3 repeated four times, eleven zeros and 2, coded with 5-bit
+        // codes for the code length symbols
+        let mut writer = BitWriter::default();
+        writer.bits(22, 14).bits(21, 5);
+        for _ in CODE_LENGTH_ORDER {
+            writer.bits(5, 3);
+        }
+        writer.code(2, 5).code(17, 5).bits(2, 3);
+        writer.code(3, 5).code(19, 5).bits(0, 2);
+        writer.code(18, 5).bits(0, 7).code(2, 5);
+        // The 2-bit codes are 00 and 01 and the 3-bit codes count up from 100
+        writer
+            .code(0b01, 2)
+            .code(0b110, 3)
+            .code(0b00, 2)
+            .code(0b100, 3);
+
+        let mut reader = BitReader::new(&writer.bytes);
+        let table = Huffman::read(&mut reader).unwrap();
+        let symbols = [0; 4].map(|_| table.decode(&mut reader).unwrap());
+        assert_eq!(symbols, [21, 8, 0, 6]);
+    }
+
+    #[test]
+    fn variable_length_numbers() {
+        let mut writer = BitWriter::default();
+        writer.bits(0b1_0100, 5).bits(0b0_0110, 5);
+        assert_eq!(decode_vlc(&mut BitReader::new(&writer.bytes), 4), 100);
+    }
+
+    #[test]
+    fn block_colors() {
+        let endpoint = Endpoint {
+            color: [16, 0, 31],
+            intensity: 3,
+        };
+        let texels = block_texels(&endpoint, &[0, 1, 2, 3].repeat(4).try_into().unwrap());
+        assert_eq!(
+            texels[..4],
+            [[90, 0, 213], [119, 0, 242], [145, 13, 255], [174, 42, 255]]
+        );
+    }
+
+    #[test]
+    fn decode_image() {
+        let mut endpoints = BitWriter::default();
+        // Color delta tables of different sizes, so that picking the wrong one desynchronizes
+        endpoints
+            .uniform_table(64)
+            .uniform_table(32)
+            .uniform_table(128);
+        endpoints.uniform_table(8).bits(0, 1);
+        // Red with the smallest intensities, then blue with the fourth
+        endpoints
+            .symbol(0, 8)
+            .symbol(15, 32)
+            .symbol(16, 32)
+            .symbol(16, 32);
+        endpoints
+            .symbol(3, 8)
+            .symbol(1, 128)
+            .symbol(0, 64)
+            .symbol(31, 64);
+
+        // Raw selectors, the first all 0 and the second counting up from 0 in each row
+        let mut selectors = BitWriter::default();
+        selectors.bits(0, 1).bits(0, 1).bits(1, 1);
+        selectors.bits(0, 32).bits(0xE4E4E4E4, 32);
+
+        let mut tables = BitWriter::default();
+        tables
+            .uniform_table(257)
+            .uniform_table(2)
+            .uniform_table(7)
+            .uniform_table(64);
+        tables.bits(4, 13);
+
+        // The 2x2 blocks use a delta, the left endpoint, a delta and the upper left endpoint. The
+        // selectors are 1, 1 again from the third history entry, 0 and 1 from the second history
+        // entry, where reusing it moved it to.
+        let mut slice = BitWriter::default();
+        slice.symbol(0b10_11_00_11, 257).symbol(1, 2).symbol(1, 7);
+        slice.symbol(4, 7);
+        slice.symbol(1, 2).symbol(0, 7);
+        slice.symbol(3, 7);
+
+        let mut global_data = Vec::new();
+        for (value, size) in [
+            (2, 2),
+            (2, 2),
+            (endpoints.bytes.len(), 4),
+            (selectors.bytes.len(), 4),
+            (tables.bytes.len(), 4),
+            (0, 4),
+            (0, 4),
+            (0, 4),
+            (slice.bytes.len(), 4),
+            (0, 4),
+            (0, 4),
+        ] {
+            global_data.extend_from_slice(&(value as u32).to_le_bytes()[..size]);
+        }
+        for bytes in [&endpoints.bytes, &selectors.bytes, &tables.bytes] {
+            global_data.extend_from_slice(bytes);
+        }
+
+        let pixels = decode(&global_data, &slice.bytes, 7, 5).unwrap();
+        let pixel = |x: usize, y: usize| &pixels[(y * 7 + x) * 4..][..4];
+        let blue = [
+            [0, 0, 213, 255],
+            [0, 0, 242, 255],
+            [13, 13, 255, 255],
+            [42, 42, 255, 255],
+        ];
+        for y in 0..5 {
+            for x in 0..7 {
+                let expected = if x < 4 && y >= 4 {
+                    &[247, 0, 0, 255]
+                } else {
+                    &blue[x % 4]
+                };
+                assert_eq!(pixel(x, y), expected, "pixel {x}, {y}");
+            }
+        }
+
+        // The same slice as alpha, which takes the green channel
+        global_data[36..40].copy_from_slice(&(slice.bytes.len() as u32).to_le_bytes());
+        let pixels = decode(&global_data, &slice.bytes, 7, 5).unwrap();
+        assert_eq!(pixels[4 * 6..][..4], [13, 13, 255, 13]);
+        assert_eq!(pixels[(4 * 7 + 3) * 4..][..4], [247, 0, 0, 0]);
+
+        assert!(decode(&global_data[..40], &slice.bytes, 7, 5).is_err());
+        assert!(decode(&global_data, &slice.bytes[..1], 7, 5).is_err());
+    }
+}
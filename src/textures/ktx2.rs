@@ -0,0 +1,83 @@
+//! Decoder for KTX2 containers as used by the `KHR_texture_basisu` glTF extension.
+//!
+//! Only the base mip level of the first layer and face is read. Level data may be supercompressed
+//! with Zstandard or zlib, and is either UASTC (transcoded to RGBA here) or one of the plain 8-bit
+//! UNORM formats. ETC1S images come with BasisLZ supercompression, whose global codebooks are
+//! needed to transcode them.
+use super::{etc1s, uastc};
+use anyhow::{anyhow, bail, Result};
+use flate2::read::ZlibDecoder;
+use image::RgbaImage;
+use ktx2::{ColorModel, DfdBlockBasic, Format, Reader, SupercompressionScheme};
+use ruzstd::decoding::StreamingDecoder;
+use std::io::Read;
+
+pub fn decode(bytes: &[u8]) -> Result<RgbaImage> {
+    let reader = Reader::new(bytes)?;
+    let header = reader.header();
+    let width = header.pixel_width;
+    let height = header.pixel_height.max(1);
+
+    let level = reader
+        .levels()
+        .next()
+        .ok_or_else(|| anyhow!("KTX2 image has no mip levels"))?;
+    let data = match header.supercompression_scheme {
+        None => level.data.to_vec(),
+        Some(SupercompressionScheme::Zstandard) => {
+            let mut data = Vec::with_capacity(level.uncompressed_byte_length as usize);
+            StreamingDecoder::new(level.data)?.read_to_end(&mut data)?;
+            data
+        }
+        Some(SupercompressionScheme::ZLIB) => {
+            let mut data = Vec::with_capacity(level.uncompressed_byte_length as usize);
+            ZlibDecoder::new(level.data).read_to_end(&mut data)?;
+            data
+        }
+        // BasisLZ slices are only decoded along with the global data
+        Some(SupercompressionScheme::BasisLZ) => level.data.to_vec(),
+        Some(scheme) => bail!("Unknown KTX2 supercompression scheme {scheme:?}"),
+    };
+
+    let rgba = match header.format {
+        None => {
+            let block = reader
+                .dfd_blocks()
+                .next()
+                .ok_or_else(|| anyhow!("KTX2 image has no data format descriptor"))?;
+            match DfdBlockBasic::parse(block.data)?.header.color_model {
+                Some(ColorModel::UASTC) => uastc::decode(&data, width, height)?,
+                Some(ColorModel::ETC1S) => {
+                    let global_data = reader.supercompression_global_data();
+                    etc1s::decode(global_data, &data, width, height)?
+                }
+                model => bail!("Unsupported KTX2 color model {model:?}"),
+            }
+        }
+        Some(Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB) => expand(&data, 4, width, height)?,
+        Some(Format::R8G8B8_UNORM | Format::R8G8B8_SRGB) => expand(&data, 3, width, height)?,
+        Some(Format::R8G8_UNORM) => expand(&data, 2, width, height)?,
+        Some(Format::R8_UNORM) => expand(&data, 1, width, height)?,
+        Some(format) => bail!("Unsupported KTX2 format {format:?}"),
+    };
+
+    RgbaImage::from_raw(width, height, rgba)
+        .ok_or_else(|| anyhow!("KTX2 image data is smaller than {width}x{height}"))
+}
+
+/// Expands tightly packed 8-bit texels with `channels` components to RGBA. Missing color channels
+/// are 0 and a missing alpha channel is opaque, like a Vulkan texture fetch would return.
+fn expand(data: &[u8], channels: usize, width: u32, height: u32) -> Result<Vec<u8>> {
+    let num_pixels = (width * height) as usize;
+    if data.len() < num_pixels * channels {
+        bail!("KTX2 image data is smaller than {width}x{height}");
+    }
+
+    let mut rgba = Vec::with_capacity(num_pixels * 4);
+    for texel in data.chunks_exact(channels).take(num_pixels) {
+        let mut pixel = [0, 0, 0, 0xFF];
+        pixel[..channels].copy_from_slice(texel);
+        rgba.extend_from_slice(&pixel);
+    }
+    Ok(rgba)
+}
@@ -0,0 +1,117 @@
+//! Reader and writer for the Portable FloatMap format, which the image crate doesn't support.
+use anyhow::{anyhow, bail, Result};
+use image::Rgba32FImage;
+
+pub fn read(bytes: &[u8]) -> Result<Rgba32FImage> {
+    // The header is 4 whitespace separated tokens followed by a single whitespace character
+    let mut tokens = Vec::with_capacity(4);
+    let mut pos = 0;
+    while tokens.len() < 4 {
+        while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+        let start = pos;
+        while bytes
+            .get(pos)
+            .is_some_and(|byte| !byte.is_ascii_whitespace())
+        {
+            pos += 1;
+        }
+        if start == pos {
+            bail!("Truncated PFM header");
+        }
+        tokens.push(std::str::from_utf8(&bytes[start..pos])?);
+    }
+    pos += 1;
+
+    let channels = match tokens[0] {
+        "PF" => 3,
+        "Pf" => 1,
+        magic => bail!("Unknown PFM magic {magic:?}"),
+    };
+    let width: usize = tokens[1].parse()?;
+    let height: usize = tokens[2].parse()?;
+    let scale: f32 = tokens[3].parse()?;
+
+    let end = width
+        .checked_mul(height)
+        .and_then(|num_pixels| num_pixels.checked_mul(channels * 4))
+        .and_then(|num_bytes| num_bytes.checked_add(pos))
+        .ok_or_else(|| anyhow!("PFM image of {width}x{height} is too large"))?;
+    let data = bytes
+        .get(pos..end)
+        .ok_or_else(|| anyhow!("PFM data is smaller than {width}x{height}"))?;
+    let values: Vec<f32> = data
+        .chunks_exact(4)
+        .map(|value| {
+            let value = value.try_into().unwrap();
+            // A negative scale marks little endian data
+            if scale < 0.0 {
+                f32::from_le_bytes(value)
+            } else {
+                f32::from_be_bytes(value)
+            }
+        })
+        .collect();
+
+    // Rows are stored from bottom to top
+    Ok(Rgba32FImage::from_fn(
+        width.try_into()?,
+        height.try_into()?,
+        |x, y| {
+            let idx = ((height - 1 - y as usize) * width + x as usize) * channels;
+            let texel = &values[idx..idx + channels];
+            match channels {
+                3 => image::Rgba([texel[0], texel[1], texel[2], 1.0]),
+                _ => image::Rgba([texel[0], texel[0], texel[0], 1.0]),
+            }
+        },
+    ))
+}
+
+/// Encodes the color channels of an image as little endian PFM data
+pub fn write(image: &Rgba32FImage) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let mut bytes = format!("PF\n{width} {height}\n-1.0\n").into_bytes();
+    for y in (0..height).rev() {
+        for x in 0..width {
+            for value in &image.get_pixel(x, y).0[..3] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let image = Rgba32FImage::from_fn(3, 2, |x, y| {
+            image::Rgba([x as f32, y as f32 * 0.5, -1.0e-3 * (x + y) as f32, 1.0])
+        });
+        assert_eq!(read(&write(&image)).unwrap(), image);
+    }
+
+    #[test]
+    fn big_endian_grayscale() {
+        let mut bytes = b"Pf\n2 1\n1.0\n".to_vec();
+        for value in [0.25f32, 4.0] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        let image = read(&bytes).unwrap();
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(1, 0).0, [4.0, 4.0, 4.0, 1.0]);
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        let mut bytes = b"PF\n2 2\n-1.0\n".to_vec();
+        bytes.extend_from_slice(&[0; 44]);
+        assert!(read(&bytes).is_err());
+        let huge = format!("PF\n{} {}\n-1.0\n", usize::MAX / 2, 3);
+        assert!(read(huge.as_bytes()).is_err());
+    }
+}
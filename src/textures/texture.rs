@@ -10,7 +10,14 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn new(context: Rc<RefCell<RenderContext>>, data: &[u8], width: u32, height: u32) -> Self {
+    /// Uploads 8 bit RGBA texels, `format` being the sRGB or linear variant of `Rgba8Unorm`
+    pub fn new(
+        context: Rc<RefCell<RenderContext>>,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
         let bcontext = context.borrow();
         let device = bcontext.device();
         let queue = bcontext.queue();
@@ -25,7 +32,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         });
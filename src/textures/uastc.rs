@@ -0,0 +1,561 @@
+//! Decoder for UASTC, the high quality block format of Basis Universal.
+//!
+//! Every 4x4 block is 128 bits and describes a subset of ASTC 4x4: a mode index, transcoding hints
+//! for other GPU formats (skipped here), an optional partition pattern, BISE-encoded endpoints and
+//! the interpolation weights.
+use anyhow::{bail, Result};
+
+struct Mode {
+    weight_bits: u32,
+    /// Index into [`BISE_RANGES`]
+    endpoint_range: usize,
+    subsets: usize,
+    planes: usize,
+    /// 2 for luminance-alpha, 3 for RGB and 4 for RGBA endpoints
+    components: usize,
+    hint_bits: u32,
+}
+
+const fn mode(
+    weight_bits: u32,
+    endpoint_range: usize,
+    subsets: usize,
+    planes: usize,
+    components: usize,
+    hint_bits: u32,
+) -> Mode {
+    Mode {
+        weight_bits,
+        endpoint_range,
+        subsets,
+        planes,
+        components,
+        hint_bits,
+    }
+}
+
+const MODE_SOLID_COLOR: usize = 8;
+
+#[rustfmt::skip]
+const MODES: [Mode; 19] = [
+    mode(4, 19, 1, 1, 3, 15),
+    mode(2, 20, 1, 1, 3, 15),
+    mode(3, 8, 2, 1, 3, 15),
+    mode(2, 7, 3, 1, 3, 15),
+    mode(2, 12, 2, 1, 3, 15),
+    mode(3, 20, 1, 1, 3, 15),
+    mode(2, 18, 1, 2, 3, 15),
+    mode(2, 12, 2, 1, 3, 15),
+    mode(0, 0, 1, 1, 4, 0),
+    mode(2, 8, 2, 1, 4, 23),
+    mode(4, 13, 1, 1, 4, 17),
+    mode(2, 13, 1, 2, 4, 17),
+    mode(3, 19, 1, 1, 4, 17),
+    mode(1, 20, 1, 2, 4, 23),
+    mode(2, 20, 1, 1, 4, 23),
+    mode(4, 20, 1, 1, 2, 23),
+    mode(2, 20, 2, 1, 2, 23),
+    mode(2, 20, 1, 2, 2, 23),
+    mode(5, 11, 1, 1, 3, 15),
+];
+
+/// The prefix code of each mode as `(code, length)`, read from the least significant bit. The
+/// last code is reserved.
+#[rustfmt::skip]
+const MODE_CODES: [(u32, u32); 20] = [
+    (0x1, 4), (0x35, 6), (0x1D, 5), (0x3, 5), (0x13, 5), (0xB, 5), (0x1B, 5), (0x7, 5),
+    (0x17, 5), (0xF, 5), (0x2, 3), (0x0, 2), (0x6, 3), (0x1F, 5), (0xD, 5), (0x5, 7),
+    (0x15, 6), (0x25, 6), (0x9, 4), (0x45, 7),
+];
+
+/// ASTC partition seeds of the 2-subset patterns shared with BC7
+#[rustfmt::skip]
+const PATTERNS_2: [u32; 30] = [
+    28, 20, 16, 29, 91, 9, 107, 72, 149, 204, 50, 114, 496, 17, 78, 39,
+    252, 828, 43, 156, 116, 210, 476, 273, 684, 359, 246, 195, 694, 524,
+];
+
+/// ASTC partition seeds of the 3-subset patterns shared with BC7
+const PATTERNS_3: [u32; 11] = [260, 74, 32, 156, 183, 15, 745, 0, 335, 902, 254];
+
+/// ASTC 2-subset partition seeds used by mode 7, which approximate BC7 3-subset patterns
+#[rustfmt::skip]
+const PATTERNS_2_FROM_3: [u32; 19] = [
+    36, 48, 61, 137, 161, 183, 226, 281, 302, 307, 479, 495, 593, 594, 605, 799,
+    812, 988, 993,
+];
+
+/// The `(trits, quints, bits)` of each ASTC integer sequence encoding range
+#[rustfmt::skip]
+const BISE_RANGES: [(u32, u32, u32); 21] = [
+    (0, 0, 1), (1, 0, 0), (0, 0, 2), (0, 1, 0), (1, 0, 1), (0, 0, 3), (0, 1, 1),
+    (1, 0, 2), (0, 0, 4), (0, 1, 2), (1, 0, 3), (0, 0, 5), (0, 1, 3), (1, 0, 4),
+    (0, 0, 6), (0, 1, 4), (1, 0, 5), (0, 0, 7), (0, 1, 5), (1, 0, 6), (0, 0, 8),
+];
+
+/// Decodes a mip level of 4x4 UASTC blocks into 8-bit RGBA pixels
+pub fn decode(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+    let blocks_x = width.div_ceil(4) as usize;
+    let blocks_y = height.div_ceil(4) as usize;
+    if data.len() < blocks_x * blocks_y * 16 {
+        bail!("UASTC data is too short for a {width}x{height} image");
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = vec![0; width * height * 4];
+    for (i, block) in data.chunks_exact(16).take(blocks_x * blocks_y).enumerate() {
+        let texels = decode_block(u128::from_le_bytes(block.try_into().unwrap()))?;
+
+        let (block_x, block_y) = (i % blocks_x * 4, i / blocks_x * 4);
+        for (j, texel) in texels.iter().enumerate() {
+            let (x, y) = (block_x + j % 4, block_y + j / 4);
+            if x < width && y < height {
+                let idx = (y * width + x) * 4;
+                pixels[idx..idx + 4].copy_from_slice(texel);
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+struct BitReader {
+    bits: u128,
+    pos: u32,
+}
+
+impl BitReader {
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.pos) as u32 & ((1 << count) - 1);
+        self.pos += count;
+        value
+    }
+}
+
+fn decode_block(bits: u128) -> Result<[[u8; 4]; 16]> {
+    let Some(mode_idx) = MODE_CODES
+        .iter()
+        .position(|&(code, len)| (bits as u32 & ((1 << len) - 1)) == code)
+    else {
+        unreachable!("the mode codes form a complete prefix code");
+    };
+    if mode_idx >= MODES.len() {
+        bail!("reserved UASTC mode");
+    }
+
+    let mut reader = BitReader {
+        bits,
+        pos: MODE_CODES[mode_idx].1,
+    };
+    if mode_idx == MODE_SOLID_COLOR {
+        let color = [0; 4].map(|_| reader.read(8) as u8);
+        return Ok([color; 16]);
+    }
+
+    let mode = &MODES[mode_idx];
+    reader.pos += mode.hint_bits;
+
+    let seed = match mode.subsets {
+        1 => None,
+        3 => PATTERNS_3.get(reader.read(4) as usize),
+        _ if mode_idx == 7 => PATTERNS_2_FROM_3.get(reader.read(5) as usize),
+        _ => PATTERNS_2.get(reader.read(5) as usize),
+    };
+    let mut subsets = [0; 16];
+    if mode.subsets > 1 {
+        let Some(&seed) = seed else {
+            bail!("invalid UASTC partition pattern");
+        };
+        for (i, subset) in subsets.iter_mut().enumerate() {
+            *subset = astc_partition(seed, i as u32 % 4, i as u32 / 4, mode.subsets as u32);
+        }
+    }
+
+    // The component interpolated with the second plane of weights
+    let plane_component = match (mode.planes, mode.components) {
+        (1, _) => None,
+        (_, 2) => Some(3),
+        _ => Some(reader.read(2) as usize),
+    };
+
+    let mut endpoints = [0; 24];
+    let endpoints = &mut endpoints[..mode.components * 2 * mode.subsets];
+    decode_bise(&mut reader, mode.endpoint_range, endpoints);
+    for endpoint in endpoints.iter_mut() {
+        *endpoint = unquantize_endpoint(mode.endpoint_range, *endpoint);
+    }
+
+    // The first texel of each subset is an anchor whose weights omit the most significant bit
+    let mut weights = [[0; 2]; 16];
+    for i in 0..16 {
+        let anchor = !subsets[..i].contains(&subsets[i]);
+        for weight in weights[i].iter_mut().take(mode.planes) {
+            let value = reader.read(mode.weight_bits - anchor as u32);
+            *weight = unquantize_weight(mode.weight_bits, value);
+        }
+    }
+
+    let mut texels = [[0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let endpoints = &endpoints[subsets[i] * mode.components * 2..];
+        for (c, channel) in texel.iter_mut().enumerate() {
+            let component = match mode.components {
+                2 if c < 3 => 0,
+                2 => 1,
+                3 if c == 3 => {
+                    *channel = 0xFF;
+                    continue;
+                }
+                _ => c,
+            };
+            let weight = weights[i][(plane_component == Some(c)) as usize];
+            *channel = interpolate(
+                endpoints[2 * component],
+                endpoints[2 * component + 1],
+                weight,
+            );
+        }
+    }
+
+    Ok(texels)
+}
+
+/// Decodes integers packed with bounded integer sequence encoding. Unlike ASTC, UASTC stores all
+/// trit or quint groups (as plain base-3 or base-5 numbers) before the low bits of each value.
+fn decode_bise(reader: &mut BitReader, range: usize, values: &mut [u32]) {
+    let (trits, quints, bits) = BISE_RANGES[range];
+    let (group_size, base) = match (trits, quints) {
+        (1, _) => (5, 3),
+        (_, 1) => (3, 5),
+        _ => (1, 1),
+    };
+
+    let mut groups = [0; 8];
+    if base > 1 {
+        let num_groups = values.len().div_ceil(group_size);
+        for (i, group) in groups.iter_mut().take(num_groups).enumerate() {
+            let count = (values.len() - i * group_size).min(group_size);
+            // Bits needed to store `count` digits
+            let group_bits = match (base, count) {
+                (3, 1) => 2,
+                (3, 2) => 4,
+                (3, 3) => 5,
+                (3, 4) => 7,
+                (3, _) => 8,
+                (_, 1) => 3,
+                (_, 2) => 5,
+                _ => 7,
+            };
+            *group = reader.read(group_bits);
+        }
+    }
+
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = reader.read(bits);
+        if base > 1 {
+            let group = &mut groups[i / group_size];
+            *value |= (*group % base) << bits;
+            *group /= base;
+        }
+    }
+}
+
+/// Maps a BISE-encoded endpoint value to 8 bits, following ASTC color unquantization
+fn unquantize_endpoint(range: usize, value: u32) -> u32 {
+    let (trits, quints, bits) = BISE_RANGES[range];
+    if trits == 0 && quints == 0 {
+        return replicate(value, bits, 8);
+    }
+
+    let digit = value >> bits;
+    let bit = |i: u32| (value >> i) & 1;
+    let a = if bit(0) == 1 { 0x1FF } else { 0 };
+    let (b, c) = match (trits, bits) {
+        (1, 1) => (0, 204),
+        (1, 2) => (bit(1) * 0b100010110, 93),
+        (1, 3) => (bit(2) * 0b100001010 + bit(1) * 0b010000101, 44),
+        (1, 4) => (
+            bit(3) * 0b100000100 + bit(2) * 0b010000010 + bit(1) * 0b001000001,
+            22,
+        ),
+        (1, 5) => (
+            bit(4) * 0b100000010
+                + bit(3) * 0b010000001
+                + bit(2) * 0b001000000
+                + bit(1) * 0b000100000,
+            11,
+        ),
+        (1, 6) => (
+            bit(5) * 0b100000001
+                + bit(4) * 0b010000000
+                + bit(3) * 0b001000000
+                + bit(2) * 0b000100000
+                + bit(1) * 0b000010000,
+            5,
+        ),
+        (0, 1) => (0, 113),
+        (0, 2) => (bit(1) * 0b100001100, 54),
+        (0, 3) => (bit(2) * 0b100000101 + bit(1) * 0b010000010, 26),
+        (0, 4) => (
+            bit(3) * 0b100000010 + bit(2) * 0b010000001 + bit(1) * 0b001000000,
+            13,
+        ),
+        (0, 5) => (
+            bit(4) * 0b100000001
+                + bit(3) * 0b010000000
+                + bit(2) * 0b001000000
+                + bit(1) * 0b000100000,
+            6,
+        ),
+        _ => unreachable!("endpoint ranges have at least 6 levels"),
+    };
+
+    let t = (digit * c + b) ^ a;
+    (a & 0x80) | (t >> 2)
+}
+
+/// Maps a weight to the 0..=64 range used for interpolation
+fn unquantize_weight(bits: u32, value: u32) -> u32 {
+    let weight = replicate(value, bits, 6);
+    if weight > 32 {
+        weight + 1
+    } else {
+        weight
+    }
+}
+
+/// Expands `value` from `from` to `to` bits by repeating its bit pattern
+fn replicate(value: u32, from: u32, to: u32) -> u32 {
+    let mut result = 0;
+    let mut filled = 0;
+    while filled < to {
+        result = result << from | value;
+        filled += from;
+    }
+    result >> (filled - to)
+}
+
+fn interpolate(low: u32, high: u32, weight: u32) -> u8 {
+    let low = low << 8 | low;
+    let high = high << 8 | high;
+    ((low * (64 - weight) + high * weight + 32) >> 14) as u8
+}
+
+/// The ASTC partition function for blocks with fewer than 31 texels
+fn astc_partition(seed: u32, x: u32, y: u32, partitions: u32) -> usize {
+    let (x, y) = (x << 1, y << 1);
+    let seed = seed + (partitions - 1) * 1024;
+    let rnum = hash52(seed);
+
+    let mut seeds = [
+        rnum,
+        rnum >> 4,
+        rnum >> 8,
+        rnum >> 12,
+        rnum >> 16,
+        rnum >> 20,
+        rnum >> 24,
+        rnum >> 28,
+    ]
+    .map(|s| (s & 0xF) * (s & 0xF));
+
+    let (sh1, sh2) = if seed & 1 != 0 {
+        (
+            if seed & 2 != 0 { 4 } else { 5 },
+            if partitions == 3 { 6 } else { 5 },
+        )
+    } else {
+        (
+            if partitions == 3 { 6 } else { 5 },
+            if seed & 2 != 0 { 4 } else { 5 },
+        )
+    };
+    for (i, s) in seeds.iter_mut().enumerate() {
+        *s >>= if i % 2 == 0 { sh1 } else { sh2 };
+    }
+
+    // The z terms vanish for 2D blocks
+    let a = (seeds[0] * x + seeds[1] * y + (rnum >> 14)) & 0x3F;
+    let b = (seeds[2] * x + seeds[3] * y + (rnum >> 10)) & 0x3F;
+    let c = if partitions < 3 {
+        0
+    } else {
+        (seeds[4] * x + seeds[5] * y + (rnum >> 6)) & 0x3F
+    };
+
+    if a >= b && a >= c {
+        0
+    } else if b >= c {
+        1
+    } else {
+        2
+    }
+}
+
+fn hash52(mut p: u32) -> u32 {
+    p ^= p >> 15;
+    p = p.wrapping_sub(p << 17);
+    p = p.wrapping_add(p << 7);
+    p = p.wrapping_add(p << 4);
+    p ^= p >> 5;
+    p = p.wrapping_add(p << 16);
+    p ^= p >> 7;
+    p ^= p >> 3;
+    p ^= p << 6;
+    p ^= p >> 17;
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs `(value, bits)` fields into a block, starting at the least significant bit
+    fn block(fields: &[(u32, u32)]) -> u128 {
+        let mut bits = 0;
+        let mut pos = 0;
+        for &(value, count) in fields {
+            bits |= (value as u128) << pos;
+            pos += count;
+        }
+        assert!(pos <= 128);
+        bits
+    }
+
+    #[test]
+    fn solid_color() {
+        let bits = block(&[MODE_CODES[8], (0x12, 8), (0x34, 8), (0x56, 8), (0x78, 8)]);
+        assert_eq!(decode_block(bits).unwrap(), [[0x12, 0x34, 0x56, 0x78]; 16]);
+    }
+
+    #[test]
+    fn single_subset_rgb() {
+        // Mode 1: 8 bit endpoints and 2 bit weights, the weight of each texel is its column
+        let mut fields = vec![MODE_CODES[1], (0, 15)];
+        fields.extend([0, 255, 255, 0, 64, 192].map(|endpoint| (endpoint, 8)));
+        fields.push((0, 1));
+        fields.extend((1..16).map(|i| (i % 4, 2)));
+
+        // Interpolated like ASTC, with the weights 0, 21, 43 and 64 out of 64
+        let row = [
+            [0, 255, 64, 255],
+            [84, 171, 106, 255],
+            [171, 84, 150, 255],
+            [255, 0, 192, 255],
+        ];
+        let texels = decode_block(block(&fields)).unwrap();
+        for (i, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, row[i % 4], "texel {i}");
+        }
+    }
+
+    #[test]
+    fn two_subsets() {
+        // Mode 2 with the pattern of BC7 partition 0, which splits the columns in halves. The 4
+        // bit endpoints go from blue to magenta on the left and from red to yellow on the right,
+        // and the 3 bit weights grow with the rows.
+        let mut fields = vec![MODE_CODES[2], (0, 15), (0, 5)];
+        fields.extend([0, 15, 0, 0, 15, 15, 15, 15, 0, 15, 0, 0].map(|endpoint| (endpoint, 4)));
+        let row_weights = [0, 3, 5, 7];
+        for i in 0..16 {
+            // Texels 0 and 2 are the anchors of the subsets
+            let bits = if i == 0 || i == 2 { 2 } else { 3 };
+            fields.push((row_weights[i / 4], bits));
+        }
+
+        #[rustfmt::skip]
+        let expected = [
+            [0, 0, 255, 255], [0, 0, 255, 255], [255, 0, 0, 255], [255, 0, 0, 255],
+            [108, 0, 255, 255], [108, 0, 255, 255], [255, 108, 0, 255], [255, 108, 0, 255],
+            [183, 0, 255, 255], [183, 0, 255, 255], [255, 183, 0, 255], [255, 183, 0, 255],
+            [255, 0, 255, 255], [255, 0, 255, 255], [255, 255, 0, 255], [255, 255, 0, 255],
+        ];
+        assert_eq!(decode_block(block(&fields)).unwrap(), expected);
+    }
+
+    #[test]
+    fn decode_clips_partial_blocks() {
+        let bits = block(&[MODE_CODES[8], (0xFF, 8), (0, 8), (0, 8), (0xFF, 8)]);
+        let pixels = decode(&bits.to_le_bytes(), 3, 2).unwrap();
+        assert_eq!(pixels, [0xFF, 0, 0, 0xFF].repeat(6));
+        assert!(decode(&[0; 15], 3, 2).is_err());
+    }
+
+    #[test]
+    fn endpoint_unquantization() {
+        // The color unquantization tables of the ASTC specification for 6 and 10 levels
+        let quant_6 = [0, 255, 51, 204, 102, 153];
+        let quant_10 = [0, 255, 28, 227, 56, 199, 84, 171, 113, 142];
+        for (range, table) in [(4, &quant_6[..]), (6, &quant_10[..])] {
+            for (value, &expected) in table.iter().enumerate() {
+                assert_eq!(unquantize_endpoint(range, value as u32), expected);
+            }
+        }
+        assert_eq!(unquantize_endpoint(8, 0b1010), 0b10101010);
+    }
+
+    #[test]
+    fn weight_unquantization() {
+        let weights = (0..4).map(|value| unquantize_weight(2, value));
+        assert!(weights.eq([0, 21, 43, 64]));
+        let weights = (0..8).map(|value| unquantize_weight(3, value));
+        assert!(weights.eq([0, 9, 18, 27, 37, 46, 55, 64]));
+    }
+
+    #[test]
+    fn modes_fit_in_block() {
+        for (i, mode) in MODES
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != MODE_SOLID_COLOR)
+        {
+            let pattern_bits = match mode.subsets {
+                1 => 0,
+                2 => 5,
+                _ => 4,
+            };
+            let plane_bits = if mode.planes == 2 && mode.components != 2 {
+                2
+            } else {
+                0
+            };
+            // The size of a bounded integer sequence from the ASTC specification
+            let num_endpoints = (mode.components * 2 * mode.subsets) as u32;
+            let (trits, quints, bits) = BISE_RANGES[mode.endpoint_range];
+            let endpoint_bits = num_endpoints * bits
+                + trits * (8 * num_endpoints).div_ceil(5)
+                + quints * (7 * num_endpoints).div_ceil(3);
+            let planes = mode.planes as u32;
+            let weight_bits = planes * (16 * mode.weight_bits - mode.subsets as u32);
+
+            let total = MODE_CODES[i].1
+                + mode.hint_bits
+                + pattern_bits
+                + plane_bits
+                + endpoint_bits
+                + weight_bits;
+            assert!(total <= 128, "mode {i} needs {total} bits");
+        }
+    }
+
+    #[test]
+    fn partitions_match_bc7() {
+        // The BC7 2-subset partitions shared with UASTC, as masks of the texels in the second
+        // subset. ASTC may number the subsets the other way around.
+        #[rustfmt::skip]
+        const BC7_MASKS: [u32; 30] = [
+            0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC,
+            0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0x008E, 0x7100, 0x08CE, 0x008C,
+            0x7310, 0x3100, 0x8CCE, 0x088C, 0x3110, 0x6666, 0x0FF0, 0xAAAA, 0xF0F0, 0xC936,
+        ];
+        for (&seed, &bc7_mask) in PATTERNS_2.iter().zip(&BC7_MASKS) {
+            let mask = (0..16).fold(0, |mask, i| {
+                mask | (astc_partition(seed, i % 4, i / 4, 2) as u32) << i
+            });
+            assert!(
+                mask == bc7_mask || mask == !bc7_mask & 0xFFFF,
+                "seed {seed} gives {mask:04X} instead of {bc7_mask:04X}"
+            );
+        }
+    }
+}
@@ -1,6 +1,7 @@
 // https://jinleili.github.io/learn-wgpu-zh/beginner/tutorial1-window
 use crate::{
     config::Config,
+    output::Output,
     render::{CopyToScreen, PostProcessor, RenderContext, Renderer},
     scene::{build_scene, Scene},
 };
@@ -37,6 +38,10 @@ struct WgpuApp {
     copy_processor: CopyToScreen,
 
     scene: Scene,
+
+    /// The output is written once the first frame has accumulated all samples
+    output: Output,
+    output_saved: bool,
 }
 
 impl WgpuApp {
@@ -68,6 +73,7 @@ impl WgpuApp {
                     max_push_constant_size: 4,
                     max_texture_dimension_2d: 4096,
                     max_binding_array_elements_per_shader_stage: 100,
+                    max_storage_buffers_per_shader_stage: 8,
                     ..wgpu::Limits::downlevel_defaults()
                 },
                 memory_hints: wgpu::MemoryHints::Performance,
@@ -99,7 +105,8 @@ impl WgpuApp {
         let scene = build_scene(context.clone(), &config)?;
 
         let renderer = Renderer::new(context.clone(), &config, scene.bind_group_layout.clone());
-        let post_processor = PostProcessor::new(context.clone(), &config);
+        let post_processor =
+            PostProcessor::new(context.clone(), &config, scene.uniform.camera.exposure());
         let copy_processor = CopyToScreen::new(context.clone(), device.clone(), &config);
 
         Ok(Self {
@@ -114,6 +121,8 @@ impl WgpuApp {
             scene,
             post_processor,
             copy_processor,
+            output: Output::new(&config),
+            output_saved: false,
         })
     }
 
@@ -171,6 +180,11 @@ impl WgpuApp {
 
         self.renderer.render(self.scene.bind_group.clone())?;
         self.post_processor.post_process();
+        if !self.output_saved {
+            pollster::block_on(self.output.save(&self.post_processor))?;
+            info!("Saved the image to {}", self.output.path());
+            self.output_saved = true;
+        }
         self.copy_processor.copy_to_screen(&output);
 
         Ok(())